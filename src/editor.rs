@@ -1,42 +1,132 @@
+mod args;
+mod config;
 mod output;
 
+pub use self::args::Args;
+
 use crossterm::{
     event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
-    terminal,
+    execute, terminal,
 };
+use std::collections::VecDeque;
+use std::io;
 use std::time::Duration;
 
-use self::output::Output;
-
-const QUIT_TIMES: u8 = 3;
+use self::config::EditorConfig;
+use self::output::{EditAction, Output};
 
 pub struct Editor {
-    reader: Reader,
     output: Output,
     quit_times: u8,
+    /// Alt と数字で入力した繰り返し回数 (次の移動や編集に使い、使ったら消す)
+    count: Option<usize>,
+    /// 次のキーを文字としてそのまま挿入する (`quoted_insert` のキーを押した直後)
+    literal_next: bool,
+    config: EditorConfig,
 }
 
 impl Editor {
-    pub fn new() -> Self {
+    /// `keys` から読んだキー入力で編集する (端末からは [`Reader`])
+    pub fn new(args: Args, keys: impl KeySource + 'static) -> Self {
+        let (mut config, warnings) = EditorConfig::load();
+        if let Some(width) = args.tab_width {
+            config.tab_width = width;
+        }
+        if let Some(soft_tabs) = args.soft_tabs {
+            config.soft_tabs = soft_tabs;
+        }
+        let output = Output::new(args, config.clone(), warnings, Box::new(keys));
+        Self::with_output(output, config)
+    }
+
+    fn with_output(output: Output, config: EditorConfig) -> Self {
         Self {
-            reader: Reader,
-            output: Output::new(),
-            quit_times: QUIT_TIMES,
+            output,
+            quit_times: config.quit_times,
+            count: None,
+            literal_next: false,
+            config,
         }
     }
 
-    pub fn init(&self) -> crossterm::Result<()> {
+    pub fn init(&mut self) -> crossterm::Result<()> {
         terminal::enable_raw_mode()?;
-        Ok(())
+        if self.config.mouse {
+            execute!(output::screen(), event::EnableMouseCapture)?;
+        }
+        self.output.open_initial_directory()
     }
 
     pub fn run(&mut self) -> crossterm::Result<bool> {
         self.output.refresh_screen()?;
-        self.process_keypress()
+        match self.output.poll_event()? {
+            Some(Event::Key(key_event)) => {
+                let running = self.process_keypress(key_event)?;
+                if !running {
+                    self.output.remove_snapshot();
+                }
+                Ok(running)
+            }
+            Some(Event::Mouse(mouse_event)) => {
+                self.output.handle_mouse(mouse_event);
+                Ok(true)
+            }
+            Some(Event::Resize(columns, rows)) => {
+                self.output.resize(columns, rows);
+                Ok(true)
+            }
+            Some(_) => Ok(true),
+            None => {
+                self.output.on_idle();
+                Ok(true)
+            }
+        }
     }
 
-    fn process_keypress(&mut self) -> crossterm::Result<bool> {
-        match self.reader.read_key()? {
+    fn process_keypress(&mut self, key_event: KeyEvent) -> crossterm::Result<bool> {
+        if self.literal_next {
+            self.literal_next = false;
+            let count = self.count.take().unwrap_or(1).max(1);
+            match literal_char(&key_event) {
+                Some(ch) => self.apply(EditAction::InsertChar(ch), count),
+                None => self
+                    .output
+                    .set_message("Cannot insert that key literally".into()),
+            }
+            return Ok(true);
+        }
+        // 数字だけは打ったまま挿入できるよう、繰り返し回数は Alt と一緒に入力する (Alt-5 ↓ で 5 行下へ)
+        if let KeyEvent {
+            code: KeyCode::Char(digit @ '0'..='9'),
+            modifiers: KeyModifiers::ALT,
+            ..
+        } = key_event
+        {
+            let digit = digit.to_digit(10).unwrap() as usize;
+            let count = self
+                .count
+                .unwrap_or(0)
+                .saturating_mul(10)
+                .saturating_add(digit);
+            self.count = Some(count);
+            self.output.set_message(format!("Count: {}", count));
+            return Ok(true);
+        }
+        let count = self.count.take().unwrap_or(1).max(1);
+        match key_event {
+            KeyEvent {
+                code, modifiers, ..
+            } if (code, modifiers) == self.config.force_quit => return Ok(false),
+            KeyEvent {
+                code, modifiers, ..
+            } if (code, modifiers) == self.config.quoted_insert => {
+                // 繰り返し回数は挿入する文字に使う
+                self.count = (count > 1).then_some(count);
+                self.literal_next = true;
+                self.output
+                    .set_message("Quoted insert: press the key to insert".into());
+                return Ok(true);
+            }
             KeyEvent {
                 code: KeyCode::Char('q'),
                 modifiers: event::KeyModifiers::CONTROL,
@@ -52,16 +142,91 @@ impl Editor {
                 }
                 return Ok(false);
             }
+            KeyEvent {
+                code: KeyCode::Char('w'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => {
+                let closed = self.output.close_buffer()?;
+                if closed {
+                    return Ok(false);
+                }
+            }
             KeyEvent {
                 code: KeyCode::Char('s'),
                 modifiers: event::KeyModifiers::CONTROL,
                 ..
-            } => self.output.save()?,
+            } => self.output.save(false)?,
+            // 変更が無くても書き込む
+            KeyEvent {
+                code: KeyCode::Char('s'),
+                modifiers,
+                ..
+            } if modifiers == KeyModifiers::CONTROL | KeyModifiers::ALT => {
+                self.output.save(true)?
+            }
             KeyEvent {
                 code: KeyCode::Char('f'),
                 modifiers: event::KeyModifiers::CONTROL,
                 ..
             } => self.output.find()?,
+            KeyEvent {
+                code: code @ (KeyCode::Char('n') | KeyCode::Char('p')),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => (0..count).for_each(|_| self.output.find_next(matches!(code, KeyCode::Char('p')))),
+            KeyEvent {
+                code: KeyCode::Char('o'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.output.list_matches()?,
+            KeyEvent {
+                code: KeyCode::Char('g'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => self.output.goto_line()?,
+            KeyEvent {
+                code: KeyCode::F(1),
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => self.output.show_help(),
+            KeyEvent {
+                code: KeyCode::F(3),
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => self.output.toggle_macro_recording(),
+            KeyEvent {
+                code: KeyCode::F(4),
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => self.output.play_macro(count),
+            KeyEvent {
+                code: KeyCode::Char('o'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => self.output.open_file()?,
+            KeyEvent {
+                code: KeyCode::Char('d'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => self.output.diff_summary(),
+            KeyEvent {
+                code: KeyCode::Char('t'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => self.output.set_syntax()?,
+            KeyEvent {
+                code: KeyCode::Char('l'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => self.output.toggle_syntax_highlight(),
+            KeyEvent {
+                code: KeyCode::Char('l'),
+                modifiers,
+                ..
+            } if modifiers == KeyModifiers::CONTROL | KeyModifiers::ALT => {
+                self.output.redraw_syntax()
+            }
             KeyEvent {
                 code:
                     direction @ (KeyCode::Up
@@ -72,63 +237,295 @@ impl Editor {
                     | KeyCode::End),
                 modifiers: KeyModifiers::NONE,
                 ..
-            } => self.output.move_cursor(direction),
+            } => (0..count).for_each(|_| self.output.move_cursor(direction)),
+            KeyEvent {
+                code:
+                    direction @ (KeyCode::Up
+                    | KeyCode::Left
+                    | KeyCode::Down
+                    | KeyCode::Right
+                    | KeyCode::Home
+                    | KeyCode::End),
+                modifiers: KeyModifiers::SHIFT,
+                ..
+            } => (0..count).for_each(|_| self.output.select_move(direction)),
+            KeyEvent {
+                code: direction @ (KeyCode::Left | KeyCode::Right),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => (0..count).for_each(|_| self.output.jump(direction == KeyCode::Right)),
+            KeyEvent {
+                code: KeyCode::Char('j'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => self.apply(EditAction::JoinLines, count),
+            // Ctrl-C は割り込みではなくコピー。プロンプトの入力中は取り消しになる (`is_cancel`)
+            KeyEvent {
+                code: code @ (KeyCode::Char('c') | KeyCode::Char('x')),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => self
+                .output
+                .copy_selection(matches!(code, KeyCode::Char('x'))),
+            KeyEvent {
+                code: KeyCode::Char('v'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => self.apply(EditAction::Paste, count),
+            KeyEvent {
+                code: KeyCode::Char('v'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.apply(EditAction::PasteAbove, count),
+            KeyEvent {
+                code: KeyCode::Char('w'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.output.copy_lines(),
+            KeyEvent {
+                code: code @ (KeyCode::Home | KeyCode::End),
+                modifiers: KeyModifiers::CONTROL,
+                ..
+            } => self.output.move_to_file_edge(code == KeyCode::End),
+            KeyEvent {
+                code: KeyCode::Char('k'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => self.apply(EditAction::DeleteToEndOfLine, count),
+            // Ctrl-Shift-K を Ctrl-K と区別できない端末のために Alt-K でも行を削除できる
+            KeyEvent {
+                code: KeyCode::Char('K' | 'k'),
+                modifiers,
+                ..
+            } if modifiers == KeyModifiers::CONTROL | KeyModifiers::SHIFT
+                || modifiers == KeyModifiers::ALT =>
+            {
+                self.apply(EditAction::DeleteLine, count)
+            }
+            KeyEvent {
+                code: KeyCode::Char('u'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => self.apply(EditAction::DeleteToStartOfLine, count),
+            KeyEvent {
+                code: KeyCode::Char('y'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => self.apply(EditAction::Yank, count),
+            KeyEvent {
+                code: KeyCode::Char('y'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.output.yank_rotate(),
+            KeyEvent {
+                code: KeyCode::Char('.'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => (0..count).for_each(|_| self.output.repeat_last_action()),
+            KeyEvent {
+                code: KeyCode::Char('t'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.apply(EditAction::InsertTimestamp, count),
+            KeyEvent {
+                code: KeyCode::Char('c'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.apply(EditAction::ToggleCase, count),
+            KeyEvent {
+                code: KeyCode::Char('q'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.apply(EditAction::ReflowParagraph, count),
+            KeyEvent {
+                code: KeyCode::Char('s'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.output.sort_lines()?,
+            KeyEvent {
+                code: KeyCode::Char('l'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.output.normalize_line_endings()?,
+            KeyEvent {
+                code: KeyCode::Char('p'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.output.preview_save(),
+            KeyEvent {
+                code: KeyCode::Char('f'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.output.show_path(),
+            KeyEvent {
+                code: KeyCode::Char('a'),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => self.output.select_all(),
+            KeyEvent {
+                code: KeyCode::Char(' '),
+                modifiers: event::KeyModifiers::CONTROL,
+                ..
+            } => self.output.set_mark(),
+            KeyEvent {
+                code: KeyCode::Char('b'),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => self.output.toggle_block_selection(),
+            KeyEvent {
+                code: KeyCode::Esc,
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => self.output.clear_selection(),
+            KeyEvent {
+                code: KeyCode::Insert,
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => self.output.toggle_insert_mode(),
+            KeyEvent {
+                code: val @ (KeyCode::PageUp | KeyCode::PageDown),
+                modifiers: KeyModifiers::NONE,
+                ..
+            } => (0..count).for_each(|_| self.output.page_up_down(val)),
             KeyEvent {
                 code: val @ (KeyCode::PageUp | KeyCode::PageDown),
+                modifiers: KeyModifiers::ALT,
+                ..
+            } => (0..count).for_each(|_| self.output.page_left_right(val == KeyCode::PageDown)),
+            KeyEvent {
+                code: KeyCode::Tab,
                 modifiers: KeyModifiers::NONE,
                 ..
-            } => self.output.page_up_down(val),
+            } => self.apply(EditAction::InsertTab, count),
+            KeyEvent {
+                code: KeyCode::BackTab,
+                ..
+            } => self.apply(EditAction::Outdent, count),
+            // Ctrl や Alt 付きの文字は割り当てが無くても挿入しない
             KeyEvent {
-                code: code @ (KeyCode::Char(..) | KeyCode::Tab),
+                code: KeyCode::Char(ch),
                 modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
                 ..
-            } => self.output.insert_char(match code {
-                KeyCode::Tab => '\t',
-                KeyCode::Char(ch) => ch,
-                _ => unimplemented!(),
-            }),
+            } => self.apply(EditAction::InsertChar(ch), count),
             KeyEvent {
                 code: key @ (KeyCode::Backspace | KeyCode::Delete),
                 modifiers: KeyModifiers::NONE,
                 ..
-            } => {
+            } => self.apply(
                 if matches!(key, KeyCode::Delete) {
-                    self.output.move_cursor(KeyCode::Right)
-                }
-                self.output.delete_char();
-            }
+                    EditAction::Delete
+                } else {
+                    EditAction::Backspace
+                },
+                count,
+            ),
             KeyEvent {
                 code: KeyCode::Enter,
                 modifiers: KeyModifiers::NONE,
                 ..
-            } => self.output.insert_newline(),
+            } => self.apply(EditAction::InsertNewline, count),
             _ => {}
         }
-        self.quit_times = QUIT_TIMES;
+        self.quit_times = self.config.quit_times;
         Ok(true)
     }
+
+    /// 編集操作を `count` 回続けて行う
+    fn apply(&mut self, action: EditAction, count: usize) {
+        (0..count).for_each(|_| self.output.apply(action));
+    }
+}
+
+/// 挿入するキーをそのままの文字にする。Ctrl と英字は制御文字 (Ctrl-A なら `\x01`) になり、
+/// Ctrl と数字は端末と同じく Ctrl-2 が NUL、Ctrl-3 から Ctrl-7 が `\x1b` から `\x1f`、
+/// Ctrl-8 が DEL になる。Tab・Enter・Esc・Backspace もそれぞれの制御文字になる
+fn literal_char(key_event: &KeyEvent) -> Option<char> {
+    match (key_event.code, key_event.modifiers) {
+        (KeyCode::Char(ch), KeyModifiers::CONTROL)
+            if ch.is_ascii_alphabetic() || "@[\\]^_".contains(ch) =>
+        {
+            Some(char::from(ch.to_ascii_uppercase() as u8 & 0x1f))
+        }
+        (KeyCode::Char(' ' | '2'), KeyModifiers::CONTROL) => Some('\0'),
+        (KeyCode::Char(ch @ '3'..='7'), KeyModifiers::CONTROL) => {
+            Some(char::from(0x1b + (ch as u8 - b'3')))
+        }
+        (KeyCode::Char('8'), KeyModifiers::CONTROL) => Some('\x7f'),
+        (KeyCode::Char(ch), KeyModifiers::NONE | KeyModifiers::SHIFT) => Some(ch),
+        (KeyCode::Tab, _) => Some('\t'),
+        (KeyCode::Enter, _) => Some('\r'),
+        (KeyCode::Esc, _) => Some('\x1b'),
+        (KeyCode::Backspace, _) => Some('\x7f'),
+        _ => None,
+    }
+}
+
+/// プロンプトや一覧での選択を取りやめるキー (Esc か Ctrl-C)。
+/// 生モードでは Ctrl-C はシグナルにならずキー入力として届くため、編集中はコピーに、
+/// 入力待ちの間は取り消しに使う
+pub fn is_cancel(key_event: &KeyEvent) -> bool {
+    matches!(
+        key_event,
+        KeyEvent {
+            code: KeyCode::Esc,
+            ..
+        } | KeyEvent {
+            code: KeyCode::Char('c'),
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        }
+    )
 }
 
 impl Drop for Editor {
     fn drop(&mut self) {
         terminal::disable_raw_mode().expect("Could not turn off raw mode");
+        if self.config.mouse {
+            execute!(output::screen(), event::DisableMouseCapture).expect("Error");
+        }
+        self.output.clear_title().expect("Error");
+        self.output.clear_cursor_style().expect("Error");
         Output::clear_screen().expect("Error");
     }
 }
 
+/// キー入力の読み込み元
+pub trait KeySource {
+    /// しばらく待ってもキー入力が無ければ `None` を返す
+    fn poll_key(&mut self) -> crossterm::Result<Option<KeyEvent>>;
+
+    /// マウスなどキー以外の入力も返す。既定ではキー入力だけを返す
+    fn poll_event(&mut self) -> crossterm::Result<Option<Event>> {
+        Ok(self.poll_key()?.map(Event::Key))
+    }
+}
+
+/// 端末からキー入力を読む
 pub struct Reader;
 
-impl Reader {
-    pub fn read_key(&self) -> crossterm::Result<KeyEvent> {
-        loop {
-            if !event::poll(Duration::from_millis(500))? {
-                continue;
-            }
+impl KeySource for Reader {
+    /// 500ms 待ってもキー入力が無ければ `None` を返す
+    fn poll_key(&mut self) -> crossterm::Result<Option<KeyEvent>> {
+        match self.poll_event()? {
+            Some(Event::Key(event)) => Ok(Some(event)),
+            _ => Ok(None),
+        }
+    }
 
-            if let Event::Key(event) = event::read()? {
-                return Ok(event);
-            }
+    fn poll_event(&mut self) -> crossterm::Result<Option<Event>> {
+        if !event::poll(Duration::from_millis(500))? {
+            return Ok(None);
         }
+        event::read().map(Some)
+    }
+}
+
+/// 決められたキー入力を順に返す。使い切ったらエラーにして編集を終える
+impl KeySource for VecDeque<KeyEvent> {
+    fn poll_key(&mut self) -> crossterm::Result<Option<KeyEvent>> {
+        self.pop_front()
+            .map(Some)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "no more keys"))
     }
 }
 
@@ -137,30 +534,50 @@ macro_rules! prompt {
     ($output:expr,$args:tt) => {
         prompt!($output, $args, callback = |_: &_, _: _, _: _| {})
     };
-    ($output:expr,$args:tt,callback = $callback:expr) => {{
-        use crate::editor::{KeyEvent, KeyModifiers, Reader};
+    ($output:expr,$args:tt,callback = $callback:expr) => {
+        prompt!(
+            $output,
+            $args,
+            callback = $callback,
+            hint = |_: &_| String::new()
+        )
+    };
+    ($output:expr,$args:tt,callback = $callback:expr,hint = $hint:expr) => {
+        prompt!(
+            $output,
+            $args,
+            callback = $callback,
+            hint = $hint,
+            default = None::<String>
+        )
+    };
+    // 入力が空のまま Enter を押すと `default` (`Option<String>`) を入力したものとして扱う
+    ($output:expr,$args:tt,callback = $callback:expr,hint = $hint:expr,default = $default:expr) => {{
+        use $crate::editor::{KeyEvent, KeyModifiers};
 
         let output: &mut Output = $output;
+        let default: Option<String> = $default;
         let mut input = String::with_capacity(32);
         loop {
-            output.set_message(format!($args, input));
+            let hint: String = $hint(&*output);
+            output.set_message(format!($args, input) + &hint);
             output.refresh_screen()?;
-            let key_event = Reader.read_key()?;
+            let key_event = output.read_key()?;
             match key_event {
                 KeyEvent {
                     code: KeyCode::Enter,
                     modifiers: KeyModifiers::NONE,
                     ..
-                } => {
-                    if !input.is_empty() {
-                        output.set_message(String::new());
-                        $callback(output, &input, KeyCode::Enter);
-                        break;
+                } if !input.is_empty() || default.is_some() => {
+                    if input.is_empty() {
+                        input = default.unwrap_or_default();
+                        $callback(output, &input, KeyCode::Null);
                     }
+                    output.set_message(String::new());
+                    $callback(output, &input, KeyCode::Enter);
+                    break;
                 }
-                KeyEvent {
-                    code: KeyCode::Esc, ..
-                } => {
+                key_event if $crate::editor::is_cancel(&key_event) => {
                     output.set_message(String::new());
                     input.clear();
                     $callback(output, &input, KeyCode::Esc);
@@ -173,15 +590,13 @@ macro_rules! prompt {
                 } => {
                     input.pop();
                 }
+                // Tab は入力しない。検索はタブを空白に展開した `render` を対象にするため
+                // タブでは一致せず、メッセージ欄でも展開されずに表示が崩れる
                 KeyEvent {
-                    code: code @ (KeyCode::Char(..) | KeyCode::Tab),
+                    code: KeyCode::Char(ch),
                     modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
                     ..
-                } => input.push(match code {
-                    KeyCode::Tab => '\t',
-                    KeyCode::Char(ch) => ch,
-                    _ => unreachable!(),
-                }),
+                } => input.push(ch),
                 _ => {}
             }
             $callback(output, &input, key_event.code);
@@ -193,3 +608,280 @@ macro_rules! prompt {
         }
     }};
 }
+
+#[macro_export]
+macro_rules! prompt_char {
+    ($output:expr,$($args:tt)*) => {{
+        use $crate::editor::KeyEvent;
+
+        let output: &mut Output = $output;
+        output.set_message(format!($($args)*));
+        output.refresh_screen()?;
+        let key_event = output.read_key()?;
+        output.set_message(String::new());
+        match key_event {
+            key_event if $crate::editor::is_cancel(&key_event) => None,
+            key_event => Some(key_event),
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::{env, fs, process};
+
+    fn ctrl(ch: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(ch), KeyModifiers::CONTROL)
+    }
+
+    fn alt(ch: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(ch), KeyModifiers::ALT)
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    /// 文字を打つキー入力 (`\n` は Enter)
+    fn typed(text: &str) -> Vec<KeyEvent> {
+        text.chars()
+            .map(|ch| match ch {
+                '\n' => key(KeyCode::Enter),
+                ch => key(KeyCode::Char(ch)),
+            })
+            .collect()
+    }
+
+    /// 既定の設定で `keys` を押し終えるまで編集し、エディタとまだ動いているかを返す
+    fn run(keys: Vec<KeyEvent>) -> (Editor, bool) {
+        let config = EditorConfig::default();
+        let output = Output::with_syntaxes(
+            Args::default(),
+            config.clone(),
+            Vec::new(),
+            Box::new(VecDeque::from(keys)),
+        );
+        let mut editor = Editor::with_output(output, config);
+        loop {
+            match editor.run() {
+                Ok(true) => {}
+                Ok(false) => return (editor, false),
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return (editor, true),
+                Err(err) => panic!("{}", err),
+            }
+        }
+    }
+
+    fn lines(editor: &Editor) -> Vec<&str> {
+        let rows = editor.output.document().editor_rows();
+        rows.row_contents
+            .iter()
+            .map(|row| row.row_content.as_str())
+            .collect()
+    }
+
+    fn cursor(editor: &Editor) -> (usize, usize) {
+        let cursor = &editor.output.document().cursor_controller;
+        (cursor.cursor_x, cursor.cursor_y)
+    }
+
+    #[test]
+    fn typed_keys_edit_the_buffer() {
+        let mut keys = typed("ab\ncd");
+        keys.extend([key(KeyCode::Backspace), key(KeyCode::Left)]);
+        keys.extend(typed("x"));
+        let (editor, running) = run(keys);
+        assert!(running);
+        assert_eq!(lines(&editor), ["ab", "xc"]);
+        assert_eq!(cursor(&editor), (1, 1));
+        assert!(editor.output.is_dirty());
+    }
+
+    #[test]
+    fn ctrl_q_quits_a_clean_buffer_without_reading_further() {
+        let (editor, running) = run(vec![ctrl('q'), key(KeyCode::Char('x'))]);
+        assert!(!running);
+        assert!(lines(&editor).iter().all(|line| line.is_empty()));
+    }
+
+    #[test]
+    fn save_as_prompt_writes_the_file() {
+        let path = env::temp_dir().join(format!("pound-save-as-test-{}.txt", process::id()));
+        let mut keys = typed("hello");
+        keys.push(ctrl('s'));
+        keys.extend(typed(&path.to_string_lossy()));
+        keys.push(key(KeyCode::Enter));
+        let (editor, running) = run(keys);
+        assert!(running);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello");
+        let filename = editor.output.document().editor_rows().filename.clone();
+        assert_eq!(filename.as_deref(), Some(path.as_path()));
+        assert!(!editor.output.is_dirty());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn save_as_prompt_is_cancelled_by_esc_and_ctrl_c() {
+        for cancel in [key(KeyCode::Esc), ctrl('c')] {
+            let mut keys = typed("x");
+            keys.push(ctrl('s'));
+            keys.extend(typed("pound-never-written.txt"));
+            keys.push(cancel);
+            keys.extend(typed("y"));
+            let (editor, running) = run(keys);
+            assert!(running);
+            assert_eq!(lines(&editor), ["xy"]);
+            assert!(editor.output.document().editor_rows().filename.is_none());
+            assert!(editor.output.is_dirty());
+            assert!(!Path::new("pound-never-written.txt").exists());
+        }
+    }
+
+    #[test]
+    fn count_prefix_repeats_the_next_key_only() {
+        let mut keys = vec![alt('3'), key(KeyCode::Char('x'))];
+        keys.extend([alt('1'), alt('2'), key(KeyCode::Char('-'))]);
+        keys.extend(typed("y"));
+        let (editor, _) = run(keys);
+        assert_eq!(lines(&editor), [format!("xxx{}y", "-".repeat(12))]);
+    }
+
+    #[test]
+    fn count_prefix_repeats_cursor_movement() {
+        let mut keys = typed("a\nb\nc\nd");
+        keys.extend([alt('2'), key(KeyCode::Up), key(KeyCode::Up)]);
+        let (editor, _) = run(keys);
+        assert_eq!(cursor(&editor), (1, 0));
+    }
+
+    #[test]
+    fn literal_char_maps_ctrl_letters_and_punctuation() {
+        assert_eq!(literal_char(&ctrl('a')), Some('\x01'));
+        assert_eq!(literal_char(&ctrl('Z')), Some('\x1a'));
+        assert_eq!(literal_char(&ctrl('[')), Some('\x1b'));
+        assert_eq!(literal_char(&ctrl('_')), Some('\x1f'));
+    }
+
+    #[test]
+    fn literal_char_maps_ctrl_digits_like_a_terminal() {
+        assert_eq!(literal_char(&ctrl(' ')), Some('\0'));
+        assert_eq!(literal_char(&ctrl('2')), Some('\0'));
+        assert_eq!(literal_char(&ctrl('3')), Some('\x1b'));
+        assert_eq!(literal_char(&ctrl('4')), Some('\x1c'));
+        assert_eq!(literal_char(&ctrl('5')), Some('\x1d'));
+        assert_eq!(literal_char(&ctrl('6')), Some('\x1e'));
+        assert_eq!(literal_char(&ctrl('7')), Some('\x1f'));
+        assert_eq!(literal_char(&ctrl('8')), Some('\x7f'));
+        assert_eq!(literal_char(&ctrl('1')), None);
+    }
+
+    #[test]
+    fn literal_char_maps_special_keys() {
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        assert_eq!(literal_char(&key(KeyCode::Char('x'))), Some('x'));
+        assert_eq!(literal_char(&key(KeyCode::Tab)), Some('\t'));
+        assert_eq!(literal_char(&key(KeyCode::Enter)), Some('\r'));
+        assert_eq!(literal_char(&key(KeyCode::Esc)), Some('\x1b'));
+        assert_eq!(literal_char(&key(KeyCode::Backspace)), Some('\x7f'));
+        assert_eq!(literal_char(&key(KeyCode::Left)), None);
+    }
+
+    #[test]
+    fn deleted_lines_in_a_row_are_yanked_together() {
+        let mut keys = typed("a\nb\nc");
+        keys.push(KeyEvent::new(KeyCode::Home, KeyModifiers::CONTROL));
+        keys.extend([alt('k'), alt('k'), ctrl('y')]);
+        let (editor, _) = run(keys);
+        assert_eq!(lines(&editor), ["a", "b", "c"]);
+        assert_eq!(cursor(&editor), (0, 2));
+    }
+
+    fn alt_key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::ALT)
+    }
+
+    #[test]
+    fn count_prefixed_horizontal_paging_moves_by_whole_screens() {
+        let mut keys = typed(&"x".repeat(400));
+        keys.extend([key(KeyCode::Home), alt('2'), alt_key(KeyCode::PageDown)]);
+        let (editor, running) = run(keys);
+        assert!(running);
+        let cursor = &editor.output.document().cursor_controller;
+        let columns = cursor.screen_columns;
+        assert_eq!(cursor.column_offset, 2 * columns);
+        assert_eq!((cursor.cursor_x, cursor.cursor_y), (2 * columns, 0));
+    }
+
+    #[test]
+    fn horizontal_paging_from_a_short_line_moves_to_a_line_that_reaches() {
+        let mut keys = typed(&format!("{}\nshort\n", "x".repeat(400)));
+        keys.push(alt_key(KeyCode::PageDown));
+        let (editor, _) = run(keys);
+        let cursor = &editor.output.document().cursor_controller;
+        let columns = cursor.screen_columns;
+        assert_eq!(cursor.column_offset, columns);
+        assert_eq!((cursor.cursor_x, cursor.cursor_y), (columns, 0));
+
+        let mut keys = typed(&format!("{}\nshort", "x".repeat(400)));
+        keys.extend([alt_key(KeyCode::PageDown), alt_key(KeyCode::PageUp)]);
+        let (editor, _) = run(keys);
+        let cursor = &editor.output.document().cursor_controller;
+        assert_eq!(cursor.column_offset, 0);
+        assert_eq!((cursor.cursor_x, cursor.cursor_y), (5, 0));
+    }
+
+    /// 60 行目までスクロールした状態のテキスト。`needle` を `at` 行目に置き、最終行は画面幅より長い
+    fn scrolled_text(at: usize) -> Vec<KeyEvent> {
+        let text: Vec<String> = (0..59)
+            .map(|i| {
+                if i == at {
+                    "needle".to_string()
+                } else {
+                    format!("line {}", i)
+                }
+            })
+            .chain([format!("{}end", "x".repeat(100))])
+            .collect();
+        typed(&text.join("\n"))
+    }
+
+    fn viewport(editor: &Editor) -> (usize, usize, usize, usize) {
+        let cursor = &editor.output.document().cursor_controller;
+        (
+            cursor.cursor_x,
+            cursor.cursor_y,
+            cursor.row_offset,
+            cursor.column_offset,
+        )
+    }
+
+    #[test]
+    fn cancelled_search_restores_the_cursor_and_scroll_position() {
+        let (editor, _) = run(scrolled_text(2));
+        let before = viewport(&editor);
+        assert!(before.2 > 2 && before.3 > 0);
+
+        let mut keys = scrolled_text(2);
+        keys.push(ctrl('f'));
+        keys.extend(typed("needle"));
+        keys.push(key(KeyCode::Esc));
+        let (editor, _) = run(keys);
+        assert_eq!(viewport(&editor), before);
+    }
+
+    #[test]
+    fn accepted_search_keeps_the_viewport_when_the_match_is_visible() {
+        let (editor, _) = run(scrolled_text(50));
+        let (_, _, row_offset, _) = viewport(&editor);
+        assert!(row_offset <= 50);
+
+        let mut keys = scrolled_text(50);
+        keys.push(ctrl('f'));
+        keys.extend(typed("needle"));
+        keys.push(key(KeyCode::Enter));
+        let (editor, _) = run(keys);
+        assert_eq!(viewport(&editor), (0, 50, row_offset, 0));
+    }
+}
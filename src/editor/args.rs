@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+
+#[derive(Default)]
+pub struct Args {
+    pub filename: Option<PathBuf>,
+    pub syntax: Option<String>,
+    pub scratch: bool,
+    pub read_stdin: bool,
+    /// 設定ファイルより優先するタブの表示幅
+    pub tab_width: Option<usize>,
+    /// 設定ファイルより優先する、Tab キーで空白を入れるか
+    pub soft_tabs: Option<bool>,
+}
+
+impl Args {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--syntax" => {
+                    parsed.syntax = Some(args.next().ok_or("--syntax requires a syntax name")?)
+                }
+                "--tab-width" => {
+                    let width = args.next().ok_or("--tab-width requires a number")?;
+                    parsed.tab_width = Some(
+                        width
+                            .parse()
+                            .ok()
+                            .filter(|width| *width > 0)
+                            .ok_or_else(|| format!("invalid tab width `{}`", width))?,
+                    )
+                }
+                "--soft-tabs" => parsed.soft_tabs = Some(true),
+                "--hard-tabs" => parsed.soft_tabs = Some(false),
+                "--scratch" => parsed.scratch = true,
+                "--read-stdin-into-scratch" => {
+                    parsed.scratch = true;
+                    parsed.read_stdin = true;
+                }
+                flag if flag.starts_with("--") => return Err(format!("unknown option `{}`", flag)),
+                _ if parsed.filename.is_some() => return Err("only one file can be opened".into()),
+                _ => parsed.filename = Some(arg.into()),
+            }
+        }
+        if parsed.scratch && parsed.filename.is_some() {
+            return Err("a scratch buffer cannot be opened with a file".into());
+        }
+        Ok(parsed)
+    }
+}
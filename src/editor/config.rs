@@ -0,0 +1,704 @@
+use std::{
+    env, fs,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    str::Chars,
+    time::Duration,
+};
+
+use crossterm::event::{KeyCode, KeyModifiers};
+use unicode_width::UnicodeWidthChar;
+
+#[derive(Clone)]
+pub struct EditorConfig {
+    pub force_quit: (KeyCode, KeyModifiers),
+    /// 次に押したキーを文字としてそのまま挿入するキー (制御文字も入れられる)
+    pub quoted_insert: (KeyCode, KeyModifiers),
+    /// 未保存の変更があるときに終了するまで Ctrl-Q を押し直す回数 (0 ならすぐ終了する)
+    pub quit_times: u8,
+    pub soft_tabs: bool,
+    /// タブの表示幅
+    pub tab_width: usize,
+    /// インデント 1 段の幅 (`None` ならタブの表示幅と同じ)。空白でのインデントと Shift-Tab で使う
+    pub indent_size: Option<usize>,
+    /// 改行したら前の行のインデントを引き継ぎ (開き括弧の後なら 1 段深くし)、
+    /// 行頭に閉じ括弧を打ったら 1 段浅くする
+    pub auto_indent: bool,
+    pub set_title: bool,
+    pub auto_reload: bool,
+    pub scroll_past_end: bool,
+    /// 最終行の次の (まだ無い) 行までカーソルを動かせる (`false` なら最終行で止まる)
+    pub move_past_last_line: bool,
+    pub highlight_trailing_whitespace: bool,
+    pub highlight_current_line: bool,
+    /// コピー・貼り付けで OS のクリップボードを優先する
+    pub system_clipboard: bool,
+    pub show_eof_marker: bool,
+    /// ファイル末尾より後ろの行に `~` を表示する (`false` なら空行にする)
+    pub show_tilde: bool,
+    /// タブの先頭に描く目印の文字 (`true` なら `│`、`None` なら空白だけで埋める)
+    pub tab_guide: Option<char>,
+    /// 右端の 1 列に表示位置を示すスクロールバーを描く
+    pub scrollbar: bool,
+    pub line_numbers: LineNumbers,
+    /// マウスのクリック・ドラッグ・ホイールを受け付ける (端末側での文字の選択はできなくなる)
+    pub mouse: bool,
+    /// この列数を超えた部分を目立たせる (`None` なら何もしない)
+    pub max_line_length: Option<usize>,
+    /// Ctrl-U で行頭ではなくインデントの後までを削除する
+    pub kill_line_to_indent: bool,
+    /// Ctrl-N で検索中に最後に使った矢印キーの向きへ進む (`false` なら常に前方へ進む)
+    pub sticky_search_direction: bool,
+    /// 権限が無くて保存できなかったときに `sudo tee` で書き込むか尋ねる
+    pub sudo_write: bool,
+    /// 保存時に行末の空白を取り除く
+    pub trim_trailing_whitespace: bool,
+    /// 保存時にファイルの最後に改行を付ける
+    pub insert_final_newline: bool,
+    /// 挿入モードのカーソルの形 (`None` なら端末の既定のまま)
+    pub cursor_style: Option<CursorStyle>,
+    /// 上書きモードのカーソルの形 (`None` なら挿入モードと同じ)
+    pub overwrite_cursor_style: Option<CursorStyle>,
+    /// Alt-T で挿入する日時の書式 (`%Y-%m-%d` のような strftime 形式の一部、UTC)
+    pub timestamp_format: String,
+    /// ヘルプを表示しておく時間 (`None` なら他のメッセージが出るまで表示する)
+    pub help_timeout: Option<Duration>,
+    /// その他のメッセージを表示しておく時間
+    pub message_timeout: Option<Duration>,
+}
+
+impl Default for EditorConfig {
+    fn default() -> Self {
+        Self {
+            force_quit: (
+                KeyCode::Char('q'),
+                KeyModifiers::CONTROL | KeyModifiers::ALT,
+            ),
+            quoted_insert: (KeyCode::Char('i'), KeyModifiers::ALT),
+            quit_times: 3,
+            soft_tabs: false,
+            tab_width: 8,
+            indent_size: None,
+            auto_indent: false,
+            set_title: true,
+            auto_reload: false,
+            scroll_past_end: false,
+            move_past_last_line: true,
+            highlight_trailing_whitespace: true,
+            highlight_current_line: false,
+            system_clipboard: false,
+            show_eof_marker: false,
+            show_tilde: true,
+            tab_guide: None,
+            scrollbar: false,
+            line_numbers: LineNumbers::Off,
+            mouse: false,
+            max_line_length: None,
+            kill_line_to_indent: false,
+            sticky_search_direction: true,
+            sudo_write: false,
+            trim_trailing_whitespace: false,
+            insert_final_newline: false,
+            cursor_style: None,
+            overwrite_cursor_style: None,
+            timestamp_format: "%Y-%m-%dT%H:%M:%SZ".into(),
+            help_timeout: Some(Duration::from_secs(5)),
+            message_timeout: Some(Duration::from_secs(5)),
+        }
+    }
+}
+
+impl EditorConfig {
+    /// 設定ディレクトリの `config.toml` を読み込む。
+    /// ファイルが無ければ既定値を使い、解釈できなかった項目は警告として返す
+    pub fn load() -> (Self, Vec<String>) {
+        let path = match config_dir() {
+            Some(dir) => dir.join("config.toml"),
+            None => return (Self::default(), Vec::new()),
+        };
+        match fs::read_to_string(&path) {
+            Ok(source) => Self::from_source(&source, &path),
+            Err(_) => (Self::default(), Vec::new()),
+        }
+    }
+
+    /// `path` から読んだ設定を解釈する。警告には `path` を付ける
+    fn from_source(source: &str, path: &Path) -> (Self, Vec<String>) {
+        let mut config = Self::default();
+        let mut warnings = Vec::new();
+
+        let entries = match parse(source) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warnings.push(format!("{}: {}", path.display(), err));
+                return (config, warnings);
+            }
+        };
+
+        for (key, value) in entries {
+            let applied = match key.as_str() {
+                "force_quit" => value
+                    .as_str()
+                    .and_then(parse_key)
+                    .map(|key| config.force_quit = key),
+                "quoted_insert" => value
+                    .as_str()
+                    .and_then(parse_key)
+                    .map(|key| config.quoted_insert = key),
+                "quit_times" => value
+                    .as_integer()
+                    .and_then(|times| u8::try_from(times).ok())
+                    .map(|times| config.quit_times = times),
+                "soft_tabs" => value.as_bool().map(|b| config.soft_tabs = b),
+                "tab_width" => value
+                    .as_integer()
+                    .and_then(|width| usize::try_from(width).ok())
+                    .filter(|width| *width > 0)
+                    .map(|width| config.tab_width = width),
+                "indent_size" => value
+                    .as_integer()
+                    .and_then(|size| usize::try_from(size).ok())
+                    .filter(|size| *size > 0)
+                    .map(|size| config.indent_size = Some(size)),
+                "auto_indent" => value.as_bool().map(|b| config.auto_indent = b),
+                "set_title" => value.as_bool().map(|b| config.set_title = b),
+                "auto_reload" => value.as_bool().map(|b| config.auto_reload = b),
+                "scroll_past_end" => value.as_bool().map(|b| config.scroll_past_end = b),
+                "move_past_last_line" => value.as_bool().map(|b| config.move_past_last_line = b),
+                "highlight_trailing_whitespace" => value
+                    .as_bool()
+                    .map(|b| config.highlight_trailing_whitespace = b),
+                "highlight_current_line" => {
+                    value.as_bool().map(|b| config.highlight_current_line = b)
+                }
+                "clipboard" => match value.as_str() {
+                    Some("system") => Some(true),
+                    Some("internal") => Some(false),
+                    _ => None,
+                }
+                .map(|b| config.system_clipboard = b),
+                "show_eof_marker" => value.as_bool().map(|b| config.show_eof_marker = b),
+                "show_tilde" => value.as_bool().map(|b| config.show_tilde = b),
+                "tab_guide" => match (value.as_bool(), value.as_str()) {
+                    (Some(b), _) => {
+                        config.tab_guide = b.then_some('│');
+                        Some(())
+                    }
+                    // 表示幅が 1 の文字でないとタブの幅がずれる
+                    (_, Some(guide)) => {
+                        let mut chars = guide.chars();
+                        match (chars.next(), chars.next()) {
+                            (Some(c), None) if c.width() == Some(1) => {
+                                config.tab_guide = Some(c);
+                                Some(())
+                            }
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                },
+                "scrollbar" => value.as_bool().map(|b| config.scrollbar = b),
+                "mouse" => value.as_bool().map(|b| config.mouse = b),
+                "max_line_length" => value
+                    .as_integer()
+                    .and_then(|limit| usize::try_from(limit).ok())
+                    .map(|limit| config.max_line_length = (limit > 0).then_some(limit)),
+                "kill_line_to_indent" => value.as_bool().map(|b| config.kill_line_to_indent = b),
+                "sticky_search_direction" => {
+                    value.as_bool().map(|b| config.sticky_search_direction = b)
+                }
+                "sudo_write" => value.as_bool().map(|b| config.sudo_write = b),
+                "trim_trailing_whitespace" => {
+                    value.as_bool().map(|b| config.trim_trailing_whitespace = b)
+                }
+                "insert_final_newline" => value.as_bool().map(|b| config.insert_final_newline = b),
+                "cursor_style" => value
+                    .as_str()
+                    .and_then(CursorStyle::parse)
+                    .map(|style| config.cursor_style = Some(style)),
+                "overwrite_cursor_style" => value
+                    .as_str()
+                    .and_then(CursorStyle::parse)
+                    .map(|style| config.overwrite_cursor_style = Some(style)),
+                "line_numbers" => value
+                    .as_str()
+                    .and_then(LineNumbers::parse)
+                    .map(|mode| config.line_numbers = mode),
+                "timestamp_format" => value
+                    .as_str()
+                    .map(|format| config.timestamp_format = format.into()),
+                "help_timeout" => value
+                    .as_integer()
+                    .and_then(seconds)
+                    .map(|timeout| config.help_timeout = timeout),
+                "message_timeout" => value
+                    .as_integer()
+                    .and_then(seconds)
+                    .map(|timeout| config.message_timeout = timeout),
+                _ => None,
+            };
+            if applied.is_none() {
+                warnings.push(format!("{}: ignored `{}`", path.display(), key));
+            }
+        }
+        (config, warnings)
+    }
+
+    pub fn indent_size(&self) -> usize {
+        self.indent_size.unwrap_or(self.tab_width)
+    }
+}
+
+/// 左端の行番号の表示方法
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LineNumbers {
+    Off,
+    /// 各行の行番号
+    Absolute,
+    /// カーソル行からの距離 (カーソル行は 0)
+    Relative,
+    /// カーソル行だけ行番号、他はカーソル行からの距離
+    Hybrid,
+}
+
+impl LineNumbers {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(Self::Off),
+            "absolute" => Some(Self::Absolute),
+            "relative" => Some(Self::Relative),
+            "hybrid" => Some(Self::Hybrid),
+            _ => None,
+        }
+    }
+}
+
+/// カーソルの形。`block`・`bar`・`underline` に `blinking-` を付けると点滅する
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct CursorStyle {
+    shape: CursorShape,
+    blinking: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CursorShape {
+    Block,
+    Underline,
+    Bar,
+}
+
+impl CursorStyle {
+    fn parse(name: &str) -> Option<Self> {
+        let (blinking, name) = match name.strip_prefix("blinking-") {
+            Some(name) => (true, name),
+            None => (false, name.strip_prefix("steady-").unwrap_or(name)),
+        };
+        let shape = match name {
+            "block" => CursorShape::Block,
+            "underline" => CursorShape::Underline,
+            "bar" => CursorShape::Bar,
+            _ => return None,
+        };
+        Some(Self { shape, blinking })
+    }
+
+    /// DECSCUSR でカーソルの形を変えるエスケープシーケンス
+    pub fn escape_sequence(self) -> String {
+        let code = match self.shape {
+            CursorShape::Block => 1,
+            CursorShape::Underline => 3,
+            CursorShape::Bar => 5,
+        } + u8::from(!self.blinking);
+        format!("\x1b[{} q", code)
+    }
+}
+
+/// 秒数を表示時間として解釈する。0 は時間切れなし
+fn seconds(value: i64) -> Option<Option<Duration>> {
+    match u64::try_from(value).ok()? {
+        0 => Some(None),
+        secs => Some(Some(Duration::from_secs(secs))),
+    }
+}
+
+/// キーを `parse_key` が解釈できる表記で表す
+pub fn key_name(code: KeyCode, modifiers: KeyModifiers) -> String {
+    let mut name = String::new();
+    for (modifier, prefix) in [
+        (KeyModifiers::CONTROL, "ctrl-"),
+        (KeyModifiers::ALT, "alt-"),
+        (KeyModifiers::SHIFT, "shift-"),
+    ] {
+        if modifiers.contains(modifier) {
+            name.push_str(prefix);
+        }
+    }
+    match code {
+        KeyCode::Esc => name.push_str("esc"),
+        KeyCode::Enter => name.push_str("enter"),
+        KeyCode::Tab => name.push_str("tab"),
+        KeyCode::Backspace => name.push_str("backspace"),
+        KeyCode::Delete => name.push_str("delete"),
+        KeyCode::Insert => name.push_str("insert"),
+        KeyCode::Home => name.push_str("home"),
+        KeyCode::End => name.push_str("end"),
+        KeyCode::PageUp => name.push_str("pageup"),
+        KeyCode::PageDown => name.push_str("pagedown"),
+        KeyCode::Up => name.push_str("up"),
+        KeyCode::Down => name.push_str("down"),
+        KeyCode::Left => name.push_str("left"),
+        KeyCode::Right => name.push_str("right"),
+        KeyCode::Char(' ') => name.push_str("space"),
+        KeyCode::Char(c) => name.push(c),
+        KeyCode::F(n) => name.push_str(&format!("f{}", n)),
+        _ => name.push('?'),
+    }
+    name
+}
+
+/// `ctrl-alt-q` や `f1` のようなキーの表記を解釈する
+pub fn parse_key(spec: &str) -> Option<(KeyCode, KeyModifiers)> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut parts: Vec<&str> = spec.split('-').collect();
+    let key = parts.pop()?.to_lowercase();
+    for part in parts {
+        modifiers |= match part.to_lowercase().as_str() {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" | "meta" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+    }
+
+    let code = match key.as_str() {
+        "esc" => KeyCode::Esc,
+        "enter" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "space" => KeyCode::Char(' '),
+        key if key.starts_with('f') && key.len() > 1 => KeyCode::F(key[1..].parse().ok()?),
+        key => {
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                _ => return None,
+            }
+        }
+    };
+    Some((code, modifiers))
+}
+
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/pound` (未設定なら `$HOME/.config/pound`)
+pub fn config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("pound"))
+}
+
+/// TOML のサブセット (`[section]` と `key = value`) を読み込み、
+/// `section.key` をキーとした組を記述順に返す
+pub fn parse(source: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut entries = Vec::new();
+    let mut section = String::new();
+    let mut lines = source.lines().enumerate();
+
+    while let Some((number, line)) = lines.next() {
+        let mut line = strip_comment(line).trim().to_string();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            section = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .ok_or_else(|| format!("line {}: invalid section header", number + 1))?;
+            continue;
+        }
+
+        // 複数行にまたがる配列はカッコが閉じるまで連結する
+        while bracket_depth(&line) > 0 {
+            match lines.next() {
+                Some((_, next)) => {
+                    line.push(' ');
+                    line.push_str(strip_comment(next).trim());
+                }
+                None => return Err(format!("line {}: unclosed array", number + 1)),
+            }
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected `key = value`", number + 1))?;
+        let key = key.trim().trim_matches('"');
+        if key.is_empty() {
+            return Err(format!("line {}: missing key", number + 1));
+        }
+
+        let mut parser = Parser {
+            chars: value.chars().peekable(),
+        };
+        let value = parser
+            .value()
+            .and_then(|value| parser.end().map(|_| value))
+            .map_err(|err| format!("line {}: {}", number + 1, err))?;
+
+        let key = if section.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", section, key)
+        };
+        entries.push((key, value));
+    }
+    Ok(entries)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' && q == '"' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+fn bracket_depth(line: &str) -> i32 {
+    let mut depth = 0;
+    let mut quote = None;
+    line.chars().for_each(|c| match quote {
+        Some(q) if c == q => quote = None,
+        Some(_) => {}
+        None if c == '"' || c == '\'' => quote = Some(c),
+        None if c == '[' => depth += 1,
+        None if c == ']' => depth -= 1,
+        None => {}
+    });
+    depth
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('"') | Some('\'') => self.string().map(Value::String),
+            Some('[') => self.array(),
+            Some(_) => self.bare(),
+            None => Err("missing value".into()),
+        }
+    }
+
+    fn end(&mut self) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            None => Ok(()),
+            Some(c) => Err(format!("unexpected `{}` after value", c)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let quote = self.chars.next().unwrap();
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                None => return Err("unterminated string".into()),
+                Some(c) if c == quote => return Ok(string),
+                Some('\\') if quote == '"' => match self.chars.next() {
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some(c @ ('"' | '\\')) => string.push(c),
+                    _ => return Err("invalid escape sequence".into()),
+                },
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.chars.next();
+        let mut values = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.chars.next_if_eq(&']').is_some() {
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Value::Array(values)),
+                _ => return Err("expected `,` or `]` in array".into()),
+            }
+        }
+    }
+
+    fn bare(&mut self) -> Result<Value, String> {
+        let mut word = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+        {
+            word.push(c);
+        }
+        match word.as_str() {
+            "true" => Ok(Value::Boolean(true)),
+            "false" => Ok(Value::Boolean(false)),
+            _ => word
+                .replace('_', "")
+                .parse()
+                .map(Value::Integer)
+                .map_err(|_| format!("invalid value `{}`", word)),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value<'a>(entries: &'a [(String, Value)], key: &str) -> &'a Value {
+        &entries.iter().find(|(k, _)| k == key).unwrap().1
+    }
+
+    #[test]
+    fn parse_reads_strings_with_escapes() {
+        let entries = parse(
+            r#"a = "tab\there \"q\" \\ \n" # comment
+b = 'no \escape # here'"#,
+        )
+        .unwrap();
+        assert_eq!(value(&entries, "a").as_str(), Some("tab\there \"q\" \\ \n"));
+        assert_eq!(value(&entries, "b").as_str(), Some("no \\escape # here"));
+    }
+
+    #[test]
+    fn parse_reads_bools_integers_and_arrays() {
+        let entries = parse(
+            "# header comment\n\
+             on = true  # trailing comment\n\
+             off = false\n\
+             n = -1_000\n\
+             list = [\n  \"x\", # first\n  \"y\",\n]\n",
+        )
+        .unwrap();
+        assert_eq!(value(&entries, "on").as_bool(), Some(true));
+        assert_eq!(value(&entries, "off").as_bool(), Some(false));
+        assert_eq!(value(&entries, "n").as_integer(), Some(-1000));
+        let list = value(&entries, "list").as_array().unwrap();
+        let list: Vec<_> = list.iter().filter_map(Value::as_str).collect();
+        assert_eq!(list, ["x", "y"]);
+    }
+
+    #[test]
+    fn parse_prefixes_keys_with_the_section() {
+        let entries = parse("top = 1\n[ keys ]\nsave = \"ctrl-s\"\n[other]\nsave = 2").unwrap();
+        let keys: Vec<_> = entries.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["top", "keys.save", "other.save"]);
+    }
+
+    #[test]
+    fn parse_reports_malformed_lines() {
+        let err = |source| parse(source).err().unwrap();
+        assert_eq!(err("a = 1\nno equals"), "line 2: expected `key = value`");
+        assert_eq!(err("[]"), "line 1: invalid section header");
+        assert_eq!(err("[open"), "line 1: invalid section header");
+        assert_eq!(err(" = 1"), "line 1: missing key");
+        assert_eq!(err("a ="), "line 1: missing value");
+        assert_eq!(err("a = \"open"), "line 1: unterminated string");
+        assert_eq!(err("a = \"\\q\""), "line 1: invalid escape sequence");
+        assert_eq!(err("a = 1 2"), "line 1: unexpected `2` after value");
+        assert_eq!(err("a = maybe"), "line 1: invalid value `maybe`");
+        assert_eq!(err("a = [1,\n2"), "line 1: unclosed array");
+    }
+
+    #[test]
+    fn from_source_warns_about_unknown_keys_and_bad_values() {
+        let path = Path::new("config.toml");
+        let (config, warnings) =
+            EditorConfig::from_source("tab_width = 4\ncolour = true\nsoft_tabs = 1", path);
+        assert_eq!(config.tab_width, 4);
+        assert!(!config.soft_tabs);
+        assert_eq!(
+            warnings,
+            [
+                "config.toml: ignored `colour`",
+                "config.toml: ignored `soft_tabs`"
+            ]
+        );
+
+        let (config, warnings) = EditorConfig::from_source("tab_width = 4\noops", path);
+        assert_eq!(config.tab_width, EditorConfig::default().tab_width);
+        assert_eq!(warnings, ["config.toml: line 2: expected `key = value`"]);
+    }
+}
@@ -1,28 +1,59 @@
 #![allow(unused)]
 
+mod action;
+mod clipboard;
 mod cursor;
+mod diff;
+mod document;
+mod finder;
 mod highlight;
+mod jump;
+mod key_macro;
+mod kill_ring;
+mod recovery;
 mod row;
 mod search;
 mod status;
+mod syntax;
+mod timestamp;
 
+use std::fs;
 use std::io::{self, stdout, Write};
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
 
 use crossterm::style::*;
-use crossterm::{event::KeyCode, execute, queue, style, terminal};
+use crossterm::{
+    event::{Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind},
+    execute, queue, style, terminal,
+};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-use crate::{prompt, syntax_struct};
+use crate::editor::args::Args;
+use crate::editor::config::{CursorStyle, EditorConfig, LineNumbers};
+use crate::editor::{is_cancel, KeySource};
+use crate::{prompt, prompt_char, syntax_struct};
 
+pub use self::action::EditAction;
+use self::clipboard::Clipboard;
+use self::document::{Document, EditOptions, Position};
 use self::highlight::SyntaxHighlight;
-use self::search::{SearchDirection, SearchIndex};
-use self::{cursor::CursorController, row::EditorRows, status::StatusMessage};
+use self::jump::JumpList;
+use self::key_macro::KeyMacro;
+use self::kill_ring::KillRing;
+use self::row::{LineEnding, SaveOptions};
+use self::search::{SearchAction, SearchDirection, SearchIndex};
+use self::syntax::SyntaxDefinition;
+use self::{row::EditorRows, status::StatusMessage};
 
 syntax_struct! {
     struct RustHighlight {
         extensions: ["rs"],
         file_type: "rust",
         comment_start: "//",
+        multiline_comment: Some(("/*", "*/")),
         keywords : {
             [Color::Red;
                 "mod","unsafe","extern","crate","use","type","struct","enum","union","const","static",
@@ -36,44 +67,200 @@ syntax_struct! {
     }
 }
 
+const CURRENT_LINE_COLOR: Color = Color::AnsiValue(236);
+
+/// `max_line_length` を超えた部分の背景色
+const LONG_LINE_COLOR: Color = Color::DarkRed;
+
+/// DECSCUSR でカーソルの形を端末の既定に戻す
+const DEFAULT_CURSOR_STYLE: &str = "\x1b[0 q";
+
+/// この間隔より短く同じ位置をクリックしたら続けてクリックしたとみなす
+const MULTI_CLICK_INTERVAL: Duration = Duration::from_millis(500);
+
+const HELP_MESSAGE: &str =
+    "HELP: Ctrl-S = Save | Ctrl-Q = Quit | Ctrl-F = Find | Ctrl-G = Go to | F1 = Help";
+
 pub struct Output {
     win_size: (usize, usize),
     editor_contents: EditorContents,
-    cursor_controller: CursorController,
-    editor_rows: EditorRows,
+    document: Document,
     status_message: StatusMessage,
-    dirty: u64,
     search_index: SearchIndex,
-    syntax_highlight: Option<Box<dyn SyntaxHighlight>>,
+    syntaxes: Vec<Rc<dyn SyntaxHighlight>>,
+    syntax_override: Option<String>,
+    /// Ctrl-L でシンタックスハイライトを切っている
+    syntax_disabled: bool,
+    title: Option<String>,
+    /// 端末に設定したカーソルの形
+    cursor_style: Option<CursorStyle>,
+    /// 直前の編集操作 (Alt-. で繰り返す)
+    last_action: Option<EditAction>,
+    jump_list: JumpList,
+    clipboard: Clipboard,
+    kill_ring: KillRing,
+    /// 直前の削除位置とその後の `dirty` (続けて削除したら同じ記録に繋げる)
+    last_kill: Option<(Position, u64)>,
+    /// 直前に貼り付けた範囲とその後の `dirty` (続けて Alt-Y を押したら古いものに置き換える)
+    last_yank: Option<(Position, Position, u64)>,
+    /// 行の代わりに描画する一覧と選択中の位置 (ファイルを開くときに使う)
+    overlay: Option<(Vec<String>, Option<usize>)>,
+    /// 起動時に指定されたディレクトリ (最初に一覧を表示する)
+    initial_directory: Option<PathBuf>,
+    /// 直前のクリックの時刻・位置・連続回数 (ダブルクリックとトリプルクリックの判定に使う)
+    last_click: Option<(Instant, Position, u8)>,
+    config: EditorConfig,
+    keys: Box<dyn KeySource>,
+    /// F3 で記録し F4 で再生するキー入力
+    key_macro: KeyMacro,
+    /// 書き出した復旧用の控えと、そのときの `dirty`
+    snapshot: Option<(PathBuf, u64)>,
 }
 
 impl Output {
-    pub fn new() -> Self {
-        let win_size = terminal::size()
-            .map(|(x, y)| (x as usize, y as usize - 2))
-            .unwrap();
-        let mut syntax_highlight = None;
-        Self {
+    pub fn new(
+        args: Args,
+        config: EditorConfig,
+        mut warnings: Vec<String>,
+        keys: Box<dyn KeySource>,
+    ) -> Self {
+        let (syntaxes, syntax_warnings) = SyntaxDefinition::load_all();
+        warnings.extend(syntax_warnings);
+        let mut output = Self::with_syntaxes(args, config, Vec::new(), keys);
+        // 先に読み込んだものほど優先されるように後ろから登録する
+        for syntax in syntaxes.into_iter().rev() {
+            output.register_syntax(syntax);
+        }
+        if let Some(name) = output.syntax_override.take() {
+            if Output::select_syntax_by_name(&output.syntaxes, &name).is_some() {
+                output.syntax_override = Some(name);
+                output.detect_syntax();
+            } else {
+                warnings.push(format!("unknown syntax `{}`", name));
+            }
+        }
+        if !warnings.is_empty() {
+            output.set_message(format!("WARNING: {}", warnings.join(" | ")));
+        }
+        output
+    }
+
+    /// 追加のシンタックスを登録して生成する (組み込みのシンタックスより優先される)
+    pub fn with_syntaxes(
+        args: Args,
+        config: EditorConfig,
+        mut syntaxes: Vec<Rc<dyn SyntaxHighlight>>,
+        keys: Box<dyn KeySource>,
+    ) -> Self {
+        syntaxes.push(Rc::new(RustHighlight::new()));
+        row::set_tab_width(config.tab_width);
+        row::set_tab_guide(config.tab_guide);
+        let win_size = terminal::size().map(Output::text_area).unwrap_or((80, 22));
+        // ディレクトリは読み込まず、起動後に一覧から開くファイルを選ばせる
+        let (filename, initial_directory) = match args.filename {
+            Some(path) if path.is_dir() => (None, Some(path)),
+            filename => (filename, None),
+        };
+        let mut output = Self {
             win_size,
             editor_contents: EditorContents::new(),
-            cursor_controller: CursorController::new(win_size),
-            editor_rows: EditorRows::new(&mut syntax_highlight),
-            status_message: StatusMessage::new(
-                "HELP: Ctrl-S = Save | Ctrl-Q = Quit | Ctrl-F = Find".into(),
+            document: Document::new(
+                if args.scratch {
+                    EditorRows::scratch(args.read_stdin)
+                } else {
+                    EditorRows::new(filename)
+                },
+                win_size,
             ),
-            dirty: 0,
+            status_message: StatusMessage::new(config.message_timeout),
             search_index: SearchIndex::new(),
-            syntax_highlight,
+            syntaxes,
+            syntax_override: args.syntax,
+            syntax_disabled: false,
+            title: None,
+            cursor_style: None,
+            last_action: None,
+            jump_list: JumpList::new(),
+            clipboard: Clipboard::new(config.system_clipboard),
+            kill_ring: KillRing::new(),
+            last_kill: None,
+            last_yank: None,
+            overlay: None,
+            last_click: None,
+            initial_directory,
+            config,
+            keys,
+            key_macro: KeyMacro::new(),
+            snapshot: None,
+        };
+        output.document.cursor_controller.past_last_row = output.config.move_past_last_line;
+        output.detect_syntax();
+        output.show_help();
+        output
+    }
+
+    /// 再生中のマクロがあればそのキーを、無ければ端末から読んだキーを返す (記録中なら記録する)
+    pub fn poll_key(&mut self) -> crossterm::Result<Option<KeyEvent>> {
+        if let Some(key_event) = self.key_macro.pop() {
+            return Ok(Some(key_event));
+        }
+        let key_event = self.keys.poll_key()?;
+        if let Some(key_event) = key_event {
+            self.key_macro.record(key_event);
+        }
+        Ok(key_event)
+    }
+
+    pub fn read_key(&mut self) -> crossterm::Result<KeyEvent> {
+        loop {
+            if let Some(key_event) = self.poll_key()? {
+                return Ok(key_event);
+            }
+        }
+    }
+
+    pub fn poll_event(&mut self) -> crossterm::Result<Option<Event>> {
+        if let Some(key_event) = self.key_macro.pop() {
+            return Ok(Some(Event::Key(key_event)));
+        }
+        let event = self.keys.poll_event()?;
+        if let Some(Event::Key(key_event)) = event {
+            self.key_macro.record(key_event);
+        }
+        Ok(event)
+    }
+
+    /// キー入力の記録を始める。記録中なら記録を終える
+    pub fn toggle_macro_recording(&mut self) {
+        if self.key_macro.is_recording() {
+            let len = self.key_macro.stop();
+            self.set_message(format!("Recorded {} keys", len));
+        } else {
+            self.key_macro.start();
+            self.set_message("Recording keys (F3 to stop)".into());
+        }
+    }
+
+    /// 最後に記録したキー入力を `times` 回再生する。記録中は自分自身を再生しないよう何もしない
+    pub fn play_macro(&mut self, times: usize) {
+        if self.key_macro.is_recording() {
+            self.key_macro.discard_last();
+            self.set_message("Cannot play a macro while recording".into());
+        } else if !self.key_macro.play(times) {
+            self.set_message("No macro recorded".into());
         }
     }
 
     pub fn clear_screen() -> crossterm::Result<()> {
-        execute!(stdout(), terminal::Clear(terminal::ClearType::All))?;
-        execute!(stdout(), crossterm::cursor::MoveTo(0, 0))
+        execute!(screen(), terminal::Clear(terminal::ClearType::All))?;
+        execute!(screen(), crossterm::cursor::MoveTo(0, 0))
     }
 
     pub fn refresh_screen(&mut self) -> crossterm::Result<()> {
-        self.cursor_controller.scroll(&self.editor_rows);
+        self.document.cursor_controller.screen_columns = self.text_columns();
+        self.document.scroll();
+        self.update_title()?;
+        self.update_cursor_style();
         queue!(
             self.editor_contents,
             crossterm::cursor::Hide,
@@ -82,8 +269,10 @@ impl Output {
         self.draw_rows();
         self.draw_status_bar();
         self.draw_message_bar();
-        let cursor_x = self.cursor_controller.render_x - self.cursor_controller.column_offset;
-        let cursor_y = self.cursor_controller.cursor_y - self.cursor_controller.row_offset;
+        let cursor_x = self.gutter_width() + self.document.cursor_controller.render_x
+            - self.document.cursor_controller.column_offset;
+        let cursor_y =
+            self.document.cursor_controller.cursor_y - self.document.cursor_controller.row_offset;
         queue!(
             self.editor_contents,
             crossterm::cursor::MoveTo(cursor_x as u16, cursor_y as u16),
@@ -92,19 +281,488 @@ impl Output {
         self.editor_contents.flush()
     }
 
+    /// ファイル名か変更状態が変わったときだけ OSC 2 で端末のタイトルを更新する。
+    /// 画面の描画と混ざらないよう `EditorContents` に積んで一緒に出力する
+    fn update_title(&mut self) -> io::Result<()> {
+        if !self.config.set_title {
+            return Ok(());
+        }
+        let title: String = format!(
+            "{}{}",
+            self.document.editor_rows().filename(),
+            if self.is_dirty() { " *" } else { "" }
+        )
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect();
+        if self.title.as_ref() == Some(&title) {
+            return Ok(());
+        }
+        write!(self.editor_contents, "\x1b]2;{}\x07", title)?;
+        self.title = Some(title);
+        Ok(())
+    }
+
+    pub fn clear_title(&self) -> io::Result<()> {
+        if self.title.is_none() {
+            return Ok(());
+        }
+        let mut screen = screen();
+        write!(screen, "\x1b]2;\x07")?;
+        screen.flush()
+    }
+
+    /// 挿入モードと上書きモードで設定された形にカーソルを変える。変わったときだけ出力する
+    fn update_cursor_style(&mut self) {
+        let style = if self.document.insert_mode {
+            self.config.cursor_style
+        } else {
+            self.config
+                .overwrite_cursor_style
+                .or(self.config.cursor_style)
+        };
+        if style == self.cursor_style {
+            return;
+        }
+        match style {
+            Some(style) => self.editor_contents.push_str(&style.escape_sequence()),
+            None => self.editor_contents.push_str(DEFAULT_CURSOR_STYLE),
+        }
+        self.cursor_style = style;
+    }
+
+    /// 終了時にカーソルの形を端末の既定に戻す
+    pub fn clear_cursor_style(&self) -> io::Result<()> {
+        if self.cursor_style.is_none() {
+            return Ok(());
+        }
+        let mut screen = screen();
+        write!(screen, "{}", DEFAULT_CURSOR_STYLE)?;
+        screen.flush()
+    }
+
     pub fn move_cursor(&mut self, direction: KeyCode) {
-        self.cursor_controller
-            .move_cursor(direction, &self.editor_rows);
+        if self.document.mark_active() || self.document.block().is_some() {
+            self.select_move(direction);
+            return;
+        }
+        self.clear_selection();
+        self.document.move_cursor(direction);
+    }
+
+    /// ファイルの先頭か、`end` なら最終行の末尾に移動する
+    pub fn move_to_file_edge(&mut self, end: bool) {
+        self.document.selection_anchor = None;
+        self.record_jump();
+        let rows = self.document.editor_rows();
+        let (x, y) = match rows.number_of_row() {
+            number_of_rows if end && number_of_rows > 0 => (
+                rows.get_editor_row(number_of_rows - 1).char_len(),
+                number_of_rows - 1,
+            ),
+            _ => (0, 0),
+        };
+        self.document.cursor_controller.cursor_x = x;
+        self.document.cursor_controller.cursor_y = y;
+    }
+
+    /// 選択範囲を広げながらカーソルを動かす
+    pub fn select_move(&mut self, direction: KeyCode) {
+        self.document.select_move(direction);
+    }
+
+    /// バッファ全体を選択し、カーソルを最終行の末尾に置く
+    pub fn select_all(&mut self) {
+        self.document.select_all();
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.document.clear_selection();
+    }
+
+    /// 現在位置にマークを置き、以降の移動で選択範囲を広げる
+    pub fn set_mark(&mut self) {
+        self.document.set_mark();
+        self.set_message("Mark set".into());
+    }
+
+    /// 矩形選択を切り替える。選択範囲が無ければ現在位置を起点にする
+    pub fn toggle_block_selection(&mut self) {
+        self.document.toggle_block_selection();
+    }
+
+    /// 左クリックでカーソルを移動し、ダブルクリックで単語、トリプルクリックで行を選択する。
+    /// ドラッグで選択範囲を広げ、ホイールで 3 行ずつ移動する
+    pub fn handle_mouse(&mut self, event: MouseEvent) {
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                let Some((x, y)) = self.mouse_position(event) else {
+                    return;
+                };
+                let count = match self.last_click {
+                    Some((time, last, count))
+                        if last == (x, y) && time.elapsed() < MULTI_CLICK_INTERVAL =>
+                    {
+                        count % 3 + 1
+                    }
+                    _ => 1,
+                };
+                self.last_click = Some((Instant::now(), (x, y), count));
+                self.document.selection_anchor = None;
+                self.document.cursor_controller.cursor_x = x;
+                self.document.cursor_controller.cursor_y = y;
+                match count {
+                    2 => self.document.select_word(),
+                    3 => self.document.select_line(),
+                    _ => {}
+                }
+            }
+            MouseEventKind::Drag(MouseButton::Left) => {
+                let Some((x, y)) = self.mouse_position(event) else {
+                    return;
+                };
+                let cursor = &mut self.document.cursor_controller;
+                self.document
+                    .selection_anchor
+                    .get_or_insert((cursor.cursor_x, cursor.cursor_y));
+                cursor.cursor_x = x;
+                cursor.cursor_y = y;
+            }
+            MouseEventKind::ScrollUp => (0..3).for_each(|_| self.move_cursor(KeyCode::Up)),
+            MouseEventKind::ScrollDown => (0..3).for_each(|_| self.move_cursor(KeyCode::Down)),
+            _ => {}
+        }
+    }
+
+    /// 画面上の位置を文字単位の `(x, y)` に変換する。本文の外なら `None`
+    fn mouse_position(&self, event: MouseEvent) -> Option<Position> {
+        let (column, row) = (event.column as usize, event.row as usize);
+        let gutter = self.gutter_width();
+        if self.overlay.is_some()
+            || row >= self.win_size.1
+            || column >= gutter + self.text_columns()
+        {
+            return None;
+        }
+        let cursor = &self.document.cursor_controller;
+        let number_of_rows = self.document.editor_rows().number_of_row();
+        let y = (row + cursor.row_offset).min(cursor.last_row(number_of_rows));
+        let x = if y < number_of_rows {
+            let render_x = column.saturating_sub(gutter) + cursor.column_offset;
+            self.document
+                .editor_rows()
+                .get_editor_row(y)
+                .get_row_content_x(render_x)
+        } else {
+            0
+        };
+        Some((x, y))
+    }
+
+    /// 選択範囲 (無ければカーソル位置の単語) を大文字→小文字→先頭だけ大文字の順に切り替える
+    pub fn toggle_case(&mut self) {
+        self.document.toggle_case();
+    }
+
+    /// 選択範囲をクリップボードにコピーする。`cut` なら選択範囲を削除する
+    pub fn copy_selection(&mut self, cut: bool) {
+        let text = match (self.document.block_text(), self.document.selection()) {
+            (Some(text), _) => text,
+            (None, Some((start, end))) => self.document.editor_rows().text_range(start, end),
+            (None, None) => {
+                self.set_message("Nothing selected".into());
+                return;
+            }
+        };
+        let len = text.chars().count();
+        match self.clipboard.copy(text) {
+            Some(note) => self.set_message(note),
+            None => self.set_message(format!(
+                "{} {} characters",
+                if cut { "Cut" } else { "Copied" },
+                len
+            )),
+        }
+        if cut {
+            self.document.delete_selection();
+        } else if self.document.mark_active() || self.document.is_block_selection() {
+            self.clear_selection();
+        }
+    }
+
+    /// 選択範囲を含む行 (選択が無ければ現在の行) をまるごと行単位でコピーする
+    pub fn copy_lines(&mut self) {
+        let number_of_rows = self.document.editor_rows().number_of_row();
+        let (first, last) = match self.document.selection() {
+            // 選択範囲が行頭で終わっていればその行は含めない
+            Some((start, end)) if end.0 == 0 && end.1 > start.1 => (start.1, end.1 - 1),
+            Some((start, end)) => (start.1, end.1),
+            None => {
+                let y = self.document.cursor_controller.cursor_y;
+                (y, y)
+            }
+        };
+        if first >= number_of_rows {
+            self.set_message("Nothing to copy".into());
+            return;
+        }
+        let last = last.min(number_of_rows - 1);
+        let text: String = (first..=last)
+            .map(|at| format!("{}\n", self.document.editor_rows().get_row(at)))
+            .collect();
+        match self.clipboard.copy_lines(text) {
+            Some(note) => self.set_message(note),
+            None => self.set_message(format!("Copied {} lines", last - first + 1)),
+        }
+        if self.document.mark_active() || self.document.is_block_selection() {
+            self.clear_selection();
+        }
+    }
+
+    /// 選択範囲を含む行を並べ替える。大文字小文字の区別と降順はプロンプトで切り替える
+    pub fn sort_lines(&mut self) -> io::Result<()> {
+        let Some((start, end)) = self.document.selection() else {
+            self.set_message("Select lines to sort".into());
+            return Ok(());
+        };
+        // 選択範囲が行頭で終わっていればその行は含めない
+        let last = if end.0 == 0 && end.1 > start.1 {
+            end.1 - 1
+        } else {
+            end.1
+        };
+
+        let mut ignore_case = false;
+        let mut reverse = false;
+        loop {
+            match prompt_char!(
+                self,
+                "Sort {} lines [{}, {}]: (c)ase / (r)everse / Enter = sort / ESC = cancel",
+                last - start.1 + 1,
+                if ignore_case {
+                    "ignore case"
+                } else {
+                    "match case"
+                },
+                if reverse { "descending" } else { "ascending" }
+            ) {
+                None => return Ok(()),
+                Some(KeyEvent {
+                    code: KeyCode::Enter,
+                    ..
+                }) => break,
+                Some(KeyEvent {
+                    code: KeyCode::Char('c'),
+                    ..
+                }) => ignore_case = !ignore_case,
+                Some(KeyEvent {
+                    code: KeyCode::Char('r'),
+                    ..
+                }) => reverse = !reverse,
+                _ => {}
+            }
+        }
+
+        self.document
+            .sort_lines(start.1, last, ignore_case, reverse);
+        Ok(())
+    }
+
+    /// 保存時の改行コードを選び、すべての行をその改行コードにそろえる
+    pub fn normalize_line_endings(&mut self) -> io::Result<()> {
+        let current = self.document.editor_rows().line_ending;
+        let mixed = if self.document.editor_rows().mixed_line_endings {
+            ", mixed"
+        } else {
+            ""
+        };
+        let line_ending = match prompt_char!(
+            self,
+            "Line endings [{}{}]: (l)f / (c)rlf / ESC = cancel",
+            current.name(),
+            mixed
+        ) {
+            Some(KeyEvent {
+                code: KeyCode::Char('l'),
+                ..
+            }) => LineEnding::Lf,
+            Some(KeyEvent {
+                code: KeyCode::Char('c'),
+                ..
+            }) => LineEnding::Crlf,
+            _ => return Ok(()),
+        };
+        self.document.set_line_ending(line_ending);
+        self.set_message(format!("Line endings set to {}", line_ending.name()));
+        Ok(())
+    }
+
+    /// カーソルから行末までを削除してキルリングに記録する。行末では次の行を連結する
+    pub fn delete_to_end_of_line(&mut self) {
+        let (y, x) = self.cursor_position();
+        let append = self.last_kill == Some(((x, y), self.document.edits_since_save()));
+        let Some(killed) = self.document.delete_to_end_of_line() else {
+            return;
+        };
+        self.kill_ring.push(killed, append);
+        self.last_kill = Some(((x, y), self.document.edits_since_save()));
+    }
+
+    /// 現在の行をまるごと削除してキルリングに記録する。続けて削除した行は同じ記録に繋げる
+    pub fn delete_line(&mut self) {
+        self.clear_selection();
+        let y = self.document.cursor_controller.cursor_y;
+        let append = self.last_kill == Some(((0, y), self.document.edits_since_save()));
+        let Some(deleted) = self.document.delete_line() else {
+            return;
+        };
+        self.kill_ring.push(deleted + "\n", append);
+        self.last_kill = Some((
+            (0, self.document.cursor_controller.cursor_y),
+            self.document.edits_since_save(),
+        ));
+    }
+
+    /// 行頭 (設定によってはインデントの後) からカーソルまでを削除してキルリングに記録する
+    pub fn delete_to_start_of_line(&mut self) {
+        let to_indent = self.config.kill_line_to_indent;
+        if let Some(killed) = self.document.delete_to_start_of_line(to_indent) {
+            self.kill_ring.push(killed, false);
+        }
+    }
+
+    /// キルリングの最新のテキストを貼り付ける
+    pub fn yank(&mut self) {
+        if let Some(text) = self.kill_ring.yank().map(String::from) {
+            self.insert_yanked(&text);
+        }
+    }
+
+    /// 直前に貼り付けたテキストをキルリングの 1 つ古いものに置き換える
+    pub fn yank_rotate(&mut self) {
+        let Some((start, end, dirty)) = self.last_yank else {
+            self.set_message("Previous command was not a yank".into());
+            return;
+        };
+        if dirty != self.document.edits_since_save() || self.cursor_position() != (end.1, end.0) {
+            self.last_yank = None;
+            self.set_message("Previous command was not a yank".into());
+            return;
+        }
+        let Some(text) = self.kill_ring.rotate().map(String::from) else {
+            return;
+        };
+        let end = self.document.replace_range(start, end, &text);
+        self.last_yank = Some((start, end, self.document.edits_since_save()));
+    }
+
+    fn insert_yanked(&mut self, text: &str) {
+        let (start, end) = self.document.insert_str(text);
+        self.last_yank = Some((start, end, self.document.edits_since_save()));
+    }
+
+    /// 現在の日時 (UTC) を `timestamp_format` の書式で挿入する
+    pub fn insert_timestamp(&mut self) {
+        let text = timestamp::format(&self.config.timestamp_format, SystemTime::now());
+        self.document.insert_str(&text);
+    }
+
+    /// クリップボードのテキストを貼り付ける (選択範囲があれば置き換える)。
+    /// 行単位でコピーしたテキストは現在の行の下 (`above` なら上) に新しい行として挿入し、
+    /// カーソルを挿入した最初の行の先頭に置く
+    pub fn paste(&mut self, above: bool) {
+        let (text, note) = self.clipboard.paste();
+        if let Some(note) = note {
+            self.set_message(note);
+        }
+        if text.is_empty() {
+            return;
+        }
+        if self.clipboard.is_linewise(&text) {
+            self.document.insert_lines(&text, above);
+        } else {
+            self.document.insert_str(&text);
+        }
+    }
+
+    /// 行内で選択されている `render` のバイト範囲と、選択が行末を越えて続くかを返す
+    fn selection_in_row(&self, at: usize) -> Option<(usize, usize, bool)> {
+        if let Some((rows, columns)) = self.document.block() {
+            let row = self.document.editor_rows().get_editor_row(at);
+            return rows.contains(&at).then(|| {
+                (
+                    row.render_index(columns.start),
+                    row.render_index(columns.end),
+                    false,
+                )
+            });
+        }
+        let (start, end) = self.document.selection()?;
+        if at < start.1 || at > end.1 {
+            return None;
+        }
+        let row = self.document.editor_rows().get_editor_row(at);
+        let render_index = |x: usize| row.render_index(row.render_x(x));
+        let from = if at == start.1 {
+            render_index(start.0)
+        } else {
+            0
+        };
+        let to = if at == end.1 {
+            render_index(end.0)
+        } else {
+            row.render.len()
+        };
+        Some((from, to, at < end.1))
+    }
+
+    fn cursor_position(&self) -> (usize, usize) {
+        (
+            self.document.cursor_controller.cursor_y,
+            self.document.cursor_controller.cursor_x,
+        )
+    }
+
+    fn record_jump(&mut self) {
+        let position = self.cursor_position();
+        self.jump_list.push(position);
+    }
+
+    /// ジャンプリストを戻るか、`forward` なら進む
+    pub fn jump(&mut self, forward: bool) {
+        let position = if forward {
+            self.jump_list.forward()
+        } else {
+            let current = self.cursor_position();
+            self.jump_list.back(current)
+        };
+        match position {
+            Some((y, x)) => {
+                self.document.selection_anchor = None;
+                self.document.cursor_controller.cursor_y = y;
+                self.document.cursor_controller.cursor_x = x;
+                self.clamp_cursor();
+            }
+            None => self.set_message("No more jumps".into()),
+        }
     }
 
     pub fn page_up_down(&mut self, code: KeyCode) {
+        self.record_jump();
+        if self.config.scroll_past_end && matches!(code, KeyCode::PageDown) {
+            self.document.scroll_past_end();
+            return;
+        }
         match code {
-            KeyCode::PageUp => self.cursor_controller.cursor_y = self.cursor_controller.row_offset,
+            KeyCode::PageUp => {
+                self.document.cursor_controller.cursor_y =
+                    self.document.cursor_controller.row_offset
+            }
             KeyCode::PageDown => {
-                self.cursor_controller.cursor_y = self
-                    .editor_rows
-                    .number_of_row()
-                    .min(self.win_size.1 + self.cursor_controller.row_offset - 1)
+                let cursor = &self.document.cursor_controller;
+                self.document.cursor_controller.cursor_y = cursor
+                    .last_row(self.document.editor_rows().number_of_row())
+                    .min(self.win_size.1 + cursor.row_offset - 1)
             }
             _ => unimplemented!(),
         }
@@ -118,184 +776,1053 @@ impl Output {
         })
     }
 
-    pub fn insert_char(&mut self, ch: char) {
-        if self.cursor_controller.cursor_y == self.editor_rows.number_of_row() {
-            self.editor_rows
-                .insert_row(self.editor_rows.number_of_row(), String::new());
-            self.dirty += 1;
-        }
-        self.editor_rows
-            .get_editor_row_mut(self.cursor_controller.cursor_y)
-            .insert_char(self.cursor_controller.cursor_x, ch);
-
-        if let Some(it) = self.syntax_highlight.as_ref() {
-            it.update_syntax(
-                self.cursor_controller.cursor_y,
-                &mut self.editor_rows.row_contents,
-            )
+    /// 画面の幅だけ左か、`right` なら右へスクロールする
+    pub fn page_left_right(&mut self, right: bool) {
+        let screen_columns = self.text_columns();
+        let rows = self.document.editor_rows();
+        let longest = rows
+            .row_contents
+            .iter()
+            .map(|row| row.display_width())
+            .max()
+            .unwrap_or(0);
+        let cursor = self.document.cursor_controller;
+        let max_offset = (longest + 1).saturating_sub(screen_columns);
+        let column_offset = if right {
+            (cursor.column_offset + screen_columns).min(max_offset)
+        } else {
+            cursor.column_offset.saturating_sub(screen_columns)
+        };
+
+        // 画面上の相対的な列を保ったままカーソルを移動先の範囲へ移す。`render_x` は
+        // `scroll` するまで更新されないので、回数を付けて続けて呼んでも狂わないよう行から求める
+        let screen_x = if cursor.cursor_y < rows.number_of_row() {
+            rows.get_editor_row(cursor.cursor_y)
+                .render_x(cursor.cursor_x)
+                .saturating_sub(cursor.column_offset)
+        } else {
+            0
+        };
+        // カーソルの行が移動先の範囲まで届かなければ、届く行のうち最も近い行へ移る
+        let target = (0..rows.number_of_row())
+            .filter(|at| rows.get_editor_row(*at).display_width() >= column_offset)
+            .min_by_key(|at| at.abs_diff(cursor.cursor_y))
+            .map(|y| {
+                let row = rows.get_editor_row(y);
+                (row.get_row_content_x(column_offset + screen_x), y)
+            });
+
+        let cursor = &mut self.document.cursor_controller;
+        cursor.column_offset = column_offset;
+        if let Some((x, y)) = target {
+            cursor.cursor_x = x;
+            cursor.cursor_y = y;
+        }
+    }
+
+    /// 編集操作を実行し、繰り返せるように記録する
+    pub fn apply(&mut self, action: EditAction) {
+        match action {
+            EditAction::InsertChar(ch) => self.insert_char(ch),
+            EditAction::InsertNewline => self.insert_newline(),
+            EditAction::InsertTab => self.insert_tab(),
+            EditAction::Outdent => self.outdent(),
+            EditAction::Backspace => self.backspace(),
+            EditAction::Delete => self.delete(),
+            EditAction::JoinLines => self.join_lines(),
+            EditAction::DeleteToEndOfLine => self.delete_to_end_of_line(),
+            EditAction::DeleteToStartOfLine => self.delete_to_start_of_line(),
+            EditAction::DeleteLine => self.delete_line(),
+            EditAction::Paste => self.paste(false),
+            EditAction::PasteAbove => self.paste(true),
+            EditAction::Yank => self.yank(),
+            EditAction::InsertTimestamp => self.insert_timestamp(),
+            EditAction::ToggleCase => self.toggle_case(),
+            EditAction::ReflowParagraph => self.reflow_paragraph(),
+        }
+        self.last_action = Some(action);
+    }
+
+    /// 直前の編集操作を現在のカーソル位置でもう一度実行する
+    pub fn repeat_last_action(&mut self) {
+        match self.last_action {
+            Some(action) => self.apply(action),
+            None => self.set_message("Nothing to repeat".into()),
         }
+    }
 
-        self.cursor_controller.cursor_x += 1;
-        self.dirty += 1;
+    pub fn insert_char(&mut self, ch: char) {
+        self.document.insert_char(ch, self.edit_options());
+    }
+
+    pub fn insert_tab(&mut self) {
+        self.document.insert_tab(self.edit_options());
+    }
+
+    pub fn outdent(&mut self) {
+        self.document.outdent(self.config.indent_size());
+    }
+
+    pub fn toggle_insert_mode(&mut self) {
+        self.document.insert_mode = !self.document.insert_mode;
     }
 
     pub fn insert_newline(&mut self) {
-        if self.cursor_controller.cursor_x == 0 {
-            self.editor_rows
-                .insert_row(self.cursor_controller.cursor_y, String::new());
-        } else {
-            let current_row = self
-                .editor_rows
-                .get_editor_row_mut(self.cursor_controller.cursor_y);
-            let new_row_content = current_row.row_content[self.cursor_controller.cursor_x..].into();
-            current_row
-                .row_content
-                .truncate(self.cursor_controller.cursor_x);
-            EditorRows::render_row(current_row);
-            self.editor_rows
-                .insert_row(self.cursor_controller.cursor_y + 1, new_row_content);
-
-            if let Some(it) = self.syntax_highlight.as_ref() {
-                it.update_syntax(
-                    self.cursor_controller.cursor_y,
-                    &mut self.editor_rows.row_contents,
-                );
-                it.update_syntax(
-                    self.cursor_controller.cursor_y + 1,
-                    &mut self.editor_rows.row_contents,
-                )
+        self.document.insert_newline(self.edit_options());
+    }
+
+    /// ソフトタブ有効時に行頭のインデント内で押されたら、前のタブ位置まで空白をまとめて消す
+    pub fn backspace(&mut self) {
+        self.document.backspace(self.edit_options());
+    }
+
+    pub fn join_lines(&mut self) {
+        self.document.selection_anchor = None;
+        self.document.join_lines();
+    }
+
+    /// カーソルのある段落を `max_line_length` (未設定なら 80) 列で折り返し直す
+    pub fn reflow_paragraph(&mut self) {
+        self.document.selection_anchor = None;
+        let width = self.config.max_line_length.unwrap_or(80);
+        if !self.document.reflow_paragraph(width) {
+            self.set_message("No paragraph to reflow".into());
+        }
+    }
+
+    /// カーソル位置の文字を削除する (選択範囲があればそれを削除する)
+    pub fn delete(&mut self) {
+        self.document.delete();
+    }
+
+    /// 変更が無く、ファイルも既にあれば書き込まない (`force` なら常に書き込む)
+    pub fn save(&mut self, force: bool) -> crossterm::Result<()> {
+        let exists = self
+            .document
+            .editor_rows()
+            .filename
+            .as_ref()
+            .is_some_and(|name| name.exists());
+        if !force && exists && !self.is_dirty() {
+            self.set_message("No changes to save".into());
+            return Ok(());
+        }
+        if self.document.editor_rows().filename.is_none() {
+            let suggested = self.suggested_filename();
+            let prompt = prompt!(
+                self,
+                "Save as : {}",
+                callback = |_: &_, _: _, _: _| {},
+                hint = |_: &_| format!(" (default: {})", suggested),
+                default = Some(suggested.clone())
+            )
+            .map(PathBuf::from);
+            let Some(prompt) = prompt else {
+                self.set_message("Save Aborted".into());
+                return Ok(());
+            };
+
+            self.document.set_filename(prompt);
+            self.detect_syntax();
+        }
+
+        let len = match self.document.save(self.save_options()) {
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied && self.config.sudo_write => {
+                match prompt_char!(self, "Permission denied. Write with sudo? (y/n)") {
+                    Some(KeyEvent {
+                        code: KeyCode::Char('y'),
+                        ..
+                    }) => match self.save_with_sudo() {
+                        Ok(len) => len,
+                        Err(err) => {
+                            self.set_message(format!("Could not write with sudo: {}", err));
+                            return Ok(());
+                        }
+                    },
+                    _ => {
+                        self.set_message("Save Aborted".into());
+                        return Ok(());
+                    }
+                }
             }
+            result => result?,
+        };
+        self.remove_snapshot();
+        self.status_message
+            .set_message(format!("{} bytes written to disk", len));
+        Ok(())
+    }
+
+    /// 生モードとマウスの受け付けを一時的にやめ、sudo がパスワードを尋ねられるようにして書き込む
+    fn save_with_sudo(&mut self) -> io::Result<usize> {
+        let (_, rows) = terminal::size()?;
+        // sudo のプロンプトはメッセージの行に出す
+        execute!(
+            screen(),
+            crossterm::cursor::MoveTo(0, rows.saturating_sub(1)),
+            terminal::Clear(terminal::ClearType::CurrentLine)
+        )?;
+        if self.config.mouse {
+            execute!(screen(), crossterm::event::DisableMouseCapture)?;
         }
-        self.cursor_controller.cursor_x = 0;
-        self.cursor_controller.cursor_y += 1;
-        self.dirty += 1;
+        terminal::disable_raw_mode()?;
+        let result = self.document.save_with_sudo(self.save_options());
+        terminal::enable_raw_mode()?;
+        if self.config.mouse {
+            execute!(screen(), crossterm::event::EnableMouseCapture)?;
+        }
+        execute!(screen(), terminal::Clear(terminal::ClearType::All))?;
+        result
     }
 
-    pub fn delete_char(&mut self) {
-        if self.cursor_controller.cursor_y == self.editor_rows.number_of_row() {
+    /// キー入力が無いときに呼ばれ、`auto_reload` が有効ならディスク上の変更を取り込む。
+    /// 未保存の変更がある場合は読み直さずに警告する
+    pub fn on_idle(&mut self) {
+        self.update_snapshot();
+        if !self.config.auto_reload || !self.document.editor_rows_mut().check_modified() {
             return;
         }
-
-        if self.cursor_controller.cursor_y == 0 && self.cursor_controller.cursor_x == 0 {
+        let filename = self.document.editor_rows().filename().to_string();
+        if self.is_dirty() {
+            self.set_message(format!(
+                "WARNING!!! {} changed on disk. Save to overwrite or quit to discard.",
+                filename
+            ));
             return;
         }
+        match self.document.reload() {
+            Ok(()) => {
+                self.detect_syntax();
+                self.set_message(format!("{} reloaded from disk", filename));
+            }
+            Err(err) => self.set_message(format!("Could not reload {}: {}", filename, err)),
+        }
+    }
 
-        let row = self
-            .editor_rows
-            .get_editor_row_mut(self.cursor_controller.cursor_y);
-        if self.cursor_controller.cursor_x > 0 {
-            row.delete_char(self.cursor_controller.cursor_x - 1);
-            self.cursor_controller.cursor_x -= 1;
-        } else {
-            let previous_row_content = self
-                .editor_rows
-                .get_row(self.cursor_controller.cursor_y - 1);
-            self.cursor_controller.cursor_x = previous_row_content.len();
-            self.editor_rows
-                .join_adjacent_rows(self.cursor_controller.cursor_y);
-            self.cursor_controller.cursor_y -= 1;
-        }
-        if let Some(it) = self.syntax_highlight.as_ref() {
-            it.update_syntax(
-                self.cursor_controller.cursor_y,
-                &mut self.editor_rows.row_contents,
-            );
-        }
-        self.dirty += 1;
-    }
-
-    pub fn save(&mut self) -> crossterm::Result<()> {
-        if matches!(self.editor_rows.filename, None) {
-            let prompt = prompt!(self, "Save as : {}").map(|it| it.into());
-            if let None = prompt {
-                self.set_message("Save Aborted".into());
+    fn clamp_cursor(&mut self) {
+        self.document.clamp_cursor();
+    }
+
+    /// カレントディレクトリのファイルを絞り込んで選び、現在のバッファの代わりに開く
+    pub fn open_file(&mut self) -> io::Result<()> {
+        if !self.close_buffer()? {
+            return Ok(());
+        }
+        let files = match finder::list_files() {
+            Ok(files) => files,
+            Err(err) => {
+                self.set_message(format!("Cannot list files: {}", err));
                 return Ok(());
             }
+        };
 
-            prompt
-                .as_ref()
-                .and_then(|path: &PathBuf| path.extension())
-                .and_then(|ext| ext.to_str())
-                .map(|ext| {
-                    Output::select_syntax(ext).map(|syntax| {
-                        let highlight = self.syntax_highlight.insert(syntax);
-                        for i in 0..self.editor_rows.number_of_row() {
-                            highlight.update_syntax(i, &mut self.editor_rows.row_contents);
-                        }
+        let chosen = self.choose_file("Open", &files)?;
+        if let Some(name) = chosen {
+            match EditorRows::open(name.clone().into()) {
+                Ok(editor_rows) => {
+                    self.replace_buffer(editor_rows);
+                    self.offer_recovery()?;
+                }
+                Err(err) => self.set_message(format!("Cannot open {}: {}", name, err)),
+            }
+        }
+        Ok(())
+    }
+
+    /// 一覧から絞り込んで 1 つ選ぶ。Tab で隠しファイルの表示を切り替える
+    fn choose_file(&mut self, label: &str, entries: &[String]) -> io::Result<Option<String>> {
+        let mut input = String::new();
+        let mut selected = 0;
+        let mut show_hidden = false;
+        let chosen = loop {
+            let matches: Vec<String> = entries
+                .iter()
+                .filter(|name| show_hidden || !name.starts_with('.') || *name == "../")
+                .filter(|name| finder::fuzzy_match(&input, name))
+                .cloned()
+                .collect();
+            selected = selected.min(matches.len().saturating_sub(1));
+            self.set_message(format!(
+                "{}: {} ({} files | Tab = {} hidden | ESC = cancel)",
+                label,
+                input,
+                matches.len(),
+                if show_hidden { "hide" } else { "show" }
+            ));
+            self.overlay = Some((matches, Some(selected)));
+            self.refresh_screen()?;
+
+            match self.read_key()? {
+                key_event if is_cancel(&key_event) => break None,
+                KeyEvent {
+                    code: KeyCode::Enter,
+                    ..
+                } => {
+                    break self.overlay.take().and_then(|(mut matches, selected)| {
+                        selected
+                            .filter(|selected| *selected < matches.len())
+                            .map(|selected| matches.swap_remove(selected))
                     })
-                });
+                }
+                KeyEvent {
+                    code: KeyCode::Up, ..
+                } => selected = selected.saturating_sub(1),
+                KeyEvent {
+                    code: KeyCode::Down,
+                    ..
+                } => selected += 1,
+                KeyEvent {
+                    code: KeyCode::Tab, ..
+                } => show_hidden = !show_hidden,
+                KeyEvent {
+                    code: KeyCode::Backspace,
+                    ..
+                } => {
+                    input.pop();
+                }
+                KeyEvent {
+                    code: KeyCode::Char(ch),
+                    modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                    ..
+                } => input.push(ch),
+                _ => {}
+            }
+        };
+        self.overlay = None;
+        self.set_message(String::new());
+        Ok(chosen)
+    }
+
+    /// 検索語を含む行を行番号とともに一覧にし、選んだ行の最初の一致へ移動する。
+    /// 一覧を表示したまま検索語を打ち直せる (前回の検索語から始める)
+    pub fn list_matches(&mut self) -> io::Result<()> {
+        let mut input = self.search_index.last_keyword.clone().unwrap_or_default();
+        let mut selected = 0;
+        let chosen = loop {
+            let rows: Vec<usize> = if input.is_empty() {
+                Vec::new()
+            } else {
+                (0..self.document.editor_rows().number_of_row())
+                    .filter(|at| {
+                        self.document
+                            .editor_rows()
+                            .get_editor_row(*at)
+                            .render
+                            .contains(&input)
+                    })
+                    .collect()
+            };
+            selected = selected.min(rows.len().saturating_sub(1));
+            let lines = rows
+                .iter()
+                .map(|at| {
+                    let render = &self.document.editor_rows().get_editor_row(*at).render;
+                    format!("{:>5}: {}", at + 1, render.trim_start())
+                })
+                .collect();
+            self.set_message(format!(
+                "Matches: {} ({} lines | Enter = jump | ESC = cancel)",
+                input,
+                rows.len()
+            ));
+            self.overlay = Some((lines, Some(selected)));
+            self.refresh_screen()?;
+
+            match self.read_key()? {
+                key_event if is_cancel(&key_event) => break None,
+                KeyEvent {
+                    code: KeyCode::Enter,
+                    ..
+                } => break rows.get(selected).copied(),
+                KeyEvent {
+                    code: KeyCode::Up, ..
+                } => selected = selected.saturating_sub(1),
+                KeyEvent {
+                    code: KeyCode::Down,
+                    ..
+                } => selected += 1,
+                KeyEvent {
+                    code: KeyCode::Backspace,
+                    ..
+                } => {
+                    input.pop();
+                }
+                KeyEvent {
+                    code: KeyCode::Char(ch),
+                    modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+                    ..
+                } => input.push(ch),
+                _ => {}
+            }
+        };
+        self.overlay = None;
+        self.set_message(String::new());
 
-            self.editor_rows.filename = prompt;
+        if let Some(at) = chosen {
+            self.record_jump();
+            self.clear_selection();
+            let index = self
+                .document
+                .editor_rows()
+                .get_render(at)
+                .find(&input)
+                .unwrap_or(0);
+            self.document.move_to_render_index(at, index);
+            self.search_index.last_keyword = Some(input);
         }
+        Ok(())
+    }
 
-        self.editor_rows.save().map(|len| {
-            self.status_message
-                .set_message(format!("{} bytes written to disk", len));
-            self.dirty = 0
-        })
+    /// 保存していない変更があれば、前回の控えから変わったときだけ復旧用の控えを書き出す。
+    /// 変更が無くなっていれば控えを消す
+    fn update_snapshot(&mut self) {
+        let rows = self.document.editor_rows();
+        let Some(filename) = rows.filename.as_ref().filter(|_| !rows.scratch) else {
+            return;
+        };
+        if !self.is_dirty() {
+            self.remove_snapshot();
+            return;
+        }
+        let dirty = self.document.edits_since_save();
+        if self.snapshot.as_ref().is_some_and(|(_, at)| *at == dirty) {
+            return;
+        }
+        let Some(path) = recovery::path(filename) else {
+            return;
+        };
+        let snapshot = recovery::Snapshot {
+            contents: rows
+                .row_contents
+                .iter()
+                .map(|row| row.row_content.as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+            cursor: (
+                self.document.cursor_controller.cursor_x,
+                self.document.cursor_controller.cursor_y,
+            ),
+        };
+        if let Err(err) = recovery::write(&path, &snapshot) {
+            self.set_message(format!("Cannot write recovery file: {}", err));
+        }
+        self.snapshot = Some((path, dirty));
     }
 
+    /// 書き出した復旧用の控えを消す (保存したときと終了するとき)
+    pub fn remove_snapshot(&mut self) {
+        if let Some((path, _)) = self.snapshot.take() {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    /// 前回異常終了したエディタが同じファイルの控えを残していれば、復旧するか尋ねる
+    pub fn offer_recovery(&mut self) -> io::Result<()> {
+        let Some(path) = self
+            .document
+            .editor_rows()
+            .filename
+            .as_deref()
+            .and_then(recovery::find)
+        else {
+            return Ok(());
+        };
+        let snapshot = match recovery::read(&path) {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                self.set_message(format!("Cannot read {}: {}", path.display(), err));
+                return Ok(());
+            }
+        };
+        let filename = self.document.editor_rows().filename().to_string();
+        match prompt_char!(
+            self,
+            "Unsaved changes to {} were found. (r)ecover / (d)iscard / ESC = keep for later",
+            filename
+        ) {
+            Some(KeyEvent {
+                code: KeyCode::Char('r'),
+                ..
+            }) => {
+                self.search_index.overlay_row = None;
+                self.document.restore(&snapshot.contents, snapshot.cursor);
+                self.set_message(format!("Recovered unsaved changes to {}", filename));
+            }
+            Some(KeyEvent {
+                code: KeyCode::Char('d'),
+                ..
+            }) => {}
+            _ => return Ok(()),
+        }
+        let _ = fs::remove_file(path);
+        Ok(())
+    }
+
+    /// 起動時にディレクトリを指定されていたら、その一覧からファイルを選んで開く。
+    /// ファイルを指定されていたら復旧用の控えが残っていないか確かめる
+    pub fn open_initial_directory(&mut self) -> io::Result<()> {
+        match self.initial_directory.take() {
+            Some(dir) => self.browse_directory(dir),
+            None => self.offer_recovery(),
+        }
+    }
+
+    /// ディレクトリの一覧を表示し、選んだファイルを開く。ディレクトリを選ぶとその中に移る
+    fn browse_directory(&mut self, dir: PathBuf) -> io::Result<()> {
+        // 親ディレクトリへ移れるよう絶対パスにしておく
+        let mut dir = dir.canonicalize().unwrap_or(dir);
+        loop {
+            let entries = match finder::list_entries(&dir) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    self.set_message(format!("Cannot list {}: {}", dir.display(), err));
+                    return Ok(());
+                }
+            };
+            let Some(name) = self.choose_file(&dir.display().to_string(), &entries)? else {
+                return Ok(());
+            };
+            let path = dir.join(&name);
+            if name == "../" {
+                dir = dir.parent().map_or(path, Path::to_path_buf);
+                continue;
+            }
+            if name.ends_with('/') {
+                dir = path;
+                continue;
+            }
+            match EditorRows::open(path) {
+                Ok(editor_rows) => {
+                    self.replace_buffer(editor_rows);
+                    self.offer_recovery()?;
+                }
+                Err(err) => self.set_message(format!("Cannot open {}: {}", name, err)),
+            }
+            return Ok(());
+        }
+    }
+
+    fn replace_buffer(&mut self, editor_rows: EditorRows) {
+        self.remove_snapshot();
+        let insert_mode = self.document.insert_mode;
+        self.document = Document::new(editor_rows, self.win_size);
+        self.document.insert_mode = insert_mode;
+        self.document.cursor_controller.past_last_row = self.config.move_past_last_line;
+        self.search_index.reset();
+        self.document.selection_anchor = None;
+        self.jump_list = JumpList::new();
+        self.detect_syntax();
+    }
+
+    /// 編集中のファイルの絶対パスをステータスに表示する (まだ無いファイルは指定されたままのパス)
+    pub fn show_path(&mut self) {
+        let message = match self.document.editor_rows().filename.as_ref() {
+            Some(path) => fs::canonicalize(path)
+                .unwrap_or_else(|_| path.clone())
+                .display()
+                .to_string(),
+            None => self.document.editor_rows().filename().to_string(),
+        };
+        self.set_message(message);
+    }
+
+    /// ディスク上の内容からの変更行数をステータスに表示する
+    pub fn diff_summary(&mut self) {
+        let edits = match self.last_saved().and_then(|time| time.elapsed().ok()) {
+            Some(elapsed) => format!(
+                "{} edits since save {}s ago",
+                self.edits_since_save(),
+                elapsed.as_secs()
+            ),
+            None => format!("{} edits since open", self.edits_since_save()),
+        };
+        match self.document.editor_rows().changes_from_disk() {
+            Ok((0, 0)) => self.set_message(format!("No changes from disk ({})", edits)),
+            Ok((added, removed)) => {
+                self.set_message(format!("+{} -{} lines changed ({})", added, removed, edits))
+            }
+            Err(err) => self.set_message(format!("Cannot diff: {}", err)),
+        }
+    }
+
+    fn edit_options(&self) -> EditOptions {
+        EditOptions {
+            soft_tabs: self.config.soft_tabs,
+            indent_size: self.config.indent_size(),
+            auto_indent: self.config.auto_indent,
+        }
+    }
+
+    fn save_options(&self) -> SaveOptions {
+        SaveOptions {
+            trim_trailing_whitespace: self.config.trim_trailing_whitespace,
+            insert_final_newline: self.config.insert_final_newline,
+        }
+    }
+
+    /// 保存したら書き込まれるバイト数と、ディスク上の内容が変わるかを書き込まずに表示する
+    pub fn preview_save(&mut self) {
+        let options = self.save_options();
+        let rows = self.document.editor_rows();
+        let contents = rows.serialized_contents(options);
+        let trimmed = if options.trim_trailing_whitespace {
+            rows.row_contents
+                .iter()
+                .filter(|row| row.row_content.trim_end().len() != row.row_content.len())
+                .count()
+        } else {
+            0
+        };
+        let on_disk = match rows.filename.as_ref().map(fs::read) {
+            None => "no file name yet",
+            Some(Err(err)) if err.kind() == io::ErrorKind::NotFound => "new file",
+            Some(Err(_)) => "cannot read the file on disk",
+            Some(Ok(bytes)) if bytes == contents.as_bytes() => "same as on disk",
+            Some(Ok(_)) => "differs from disk",
+        };
+        let message = format!(
+            "Save would write {} bytes ({}{}{}), {} lines trimmed, {}",
+            contents.len(),
+            rows.line_ending.name(),
+            if rows.has_bom() { ", BOM" } else { "" },
+            if rows.mixed_line_endings {
+                ", mixed line endings unified"
+            } else {
+                ""
+            },
+            trimmed,
+            on_disk
+        );
+        self.set_message(message);
+    }
+
+    /// 現在のバッファを閉じてよければ `true` を返す。
+    /// バッファは 1 つしか持たないため、閉じるとエディタが終了する
+    pub fn close_buffer(&mut self) -> io::Result<bool> {
+        if !self.is_dirty() {
+            return Ok(true);
+        }
+
+        let filename = self.document.editor_rows().filename().to_string();
+        match prompt_char!(
+            self,
+            "Save changes to {}? (y)es / (n)o / ESC cancel",
+            filename
+        ) {
+            Some(KeyEvent {
+                code: KeyCode::Char('y'),
+                ..
+            }) => {
+                self.save(false)?;
+                Ok(!self.is_dirty())
+            }
+            Some(KeyEvent {
+                code: KeyCode::Char('n'),
+                ..
+            }) => Ok(true),
+            _ => Ok(false),
+        }
+    }
+
+    /// 未保存の変更があり、終了時に確認が必要かどうか (スクラッチバッファは確認しない)
     pub fn is_dirty(&self) -> bool {
-        self.dirty > 0
+        self.document.is_dirty()
+    }
+
+    #[cfg(test)]
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    /// 最後に保存して (開いて) からの編集回数。`is_dirty` と違いスクラッチバッファでも数える
+    pub fn edits_since_save(&self) -> u64 {
+        self.document.edits_since_save()
+    }
+
+    /// このセッションで最後に保存した時刻
+    pub fn last_saved(&self) -> Option<SystemTime> {
+        self.document.last_saved()
     }
 
     pub fn set_message(&mut self, message: String) {
         self.status_message.set_message(message)
     }
 
+    pub fn show_help(&mut self) {
+        self.status_message
+            .set_message_for(HELP_MESSAGE.into(), self.config.help_timeout);
+    }
+
+    /// 名前の無いバッファを保存するときに提案するファイル名 (シンタックスの拡張子を付ける)
+    fn suggested_filename(&self) -> String {
+        let extension = self
+            .document
+            .syntax_highlight
+            .as_ref()
+            .and_then(|highlight| highlight.extensions().first().cloned())
+            .unwrap_or_else(|| "txt".into());
+        format!("untitled.{}", extension)
+    }
+
+    /// 入力に合わせて一致へ移動しながら検索する。Esc ではカーソルとスクロール位置を検索前に戻す。
+    /// 空の入力で Enter を押すと前回の検索語で検索する
     pub fn find(&mut self) -> io::Result<()> {
-        let cursor_controller = self.cursor_controller;
-        if prompt!(
+        let cursor_controller = self.document.cursor_controller;
+        self.search_index.start_y = cursor_controller.cursor_y;
+        self.search_index.start_x = cursor_controller.render_x;
+        let previous = self.search_index.last_keyword.clone();
+        self.search_index.last_direction = SearchDirection::Forward;
+        let keyword = prompt!(
             self,
-            "Search: {} (Use ESC / Arrows / Enter)",
-            callback = Output::find_callback
-        )
-        .is_none()
-        {
-            self.cursor_controller = cursor_controller;
+            "Search: {}",
+            callback = Output::find_callback,
+            hint = Output::find_hint,
+            default = previous
+        );
+        if keyword.is_none() {
+            self.document.cursor_controller = cursor_controller;
+            return Ok(());
+        }
+        self.search_index.last_keyword = keyword;
+        if self.cursor_position() != (cursor_controller.cursor_y, cursor_controller.cursor_x) {
+            self.jump_list
+                .push((cursor_controller.cursor_y, cursor_controller.cursor_x));
         }
         Ok(())
     }
 
-    pub fn select_syntax(extension: &str) -> Option<Box<dyn SyntaxHighlight>> {
-        let list: Vec<Box<dyn SyntaxHighlight>> = vec![Box::new(RustHighlight::new())];
-        list.into_iter()
-            .find(|it| it.extensions().contains(&extension))
+    pub fn goto_line(&mut self) -> io::Result<()> {
+        let input = match prompt!(self, "Go to line: {} (N / N% / $ / +N / -N)") {
+            None => return Ok(()),
+            Some(input) => input,
+        };
+
+        match Output::goto_target(
+            input.trim(),
+            self.document.cursor_controller.cursor_y,
+            self.document.editor_rows().number_of_row(),
+        ) {
+            Some(y) => {
+                self.record_jump();
+                self.document.cursor_controller.cursor_y = y;
+                let row_len = if y < self.document.editor_rows().number_of_row() {
+                    self.document.editor_rows().get_editor_row(y).char_len()
+                } else {
+                    0
+                };
+                self.document.cursor_controller.cursor_x =
+                    self.document.cursor_controller.cursor_x.min(row_len);
+            }
+            None => self.set_message(format!("Invalid line: {}", input)),
+        }
+        Ok(())
+    }
+
+    /// `N` は絶対行 (1 始まり), `N%` はファイル中の割合, `$` は最終行,
+    /// `+N` / `-N` は現在行からの相対移動
+    fn goto_target(input: &str, current: usize, number_of_rows: usize) -> Option<usize> {
+        let last = number_of_rows.saturating_sub(1);
+        let target = if input == "$" {
+            last
+        } else if let Some(percent) = input.strip_suffix('%') {
+            let percent = percent.parse::<usize>().ok()?.min(100);
+            (number_of_rows * percent / 100).saturating_sub(1)
+        } else if let Some(offset) = input.strip_prefix('+') {
+            current.saturating_add(offset.parse().ok()?)
+        } else if let Some(offset) = input.strip_prefix('-') {
+            current.saturating_sub(offset.parse().ok()?)
+        } else {
+            input.parse::<usize>().ok()?.saturating_sub(1)
+        };
+        Some(target.min(last))
+    }
+
+    pub fn select_syntax(
+        syntaxes: &[Rc<dyn SyntaxHighlight>],
+        extension: &str,
+    ) -> Option<Rc<dyn SyntaxHighlight>> {
+        syntaxes
+            .iter()
+            .find(|it| it.extensions().iter().any(|ext| ext == extension))
+            .cloned()
+    }
+
+    /// 1 行目のシバン (`#!/bin/sh` や `#!/usr/bin/env python3`) のインタプリタから `file_type` を推測する
+    fn shebang_file_type(line: &str) -> Option<&str> {
+        let mut words = line.strip_prefix("#!")?.split_whitespace();
+        let mut interpreter = words.next()?.rsplit('/').next()?;
+        if interpreter == "env" {
+            interpreter = words.find(|word| !word.starts_with('-'))?;
+        }
+        let name = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+        Some(match name {
+            "sh" | "bash" | "dash" | "zsh" | "ksh" => "shell",
+            "node" | "nodejs" => "javascript",
+            _ => name,
+        })
+    }
+
+    pub fn select_syntax_by_name(
+        syntaxes: &[Rc<dyn SyntaxHighlight>],
+        file_type: &str,
+    ) -> Option<Rc<dyn SyntaxHighlight>> {
+        syntaxes
+            .iter()
+            .find(|it| it.file_type().eq_ignore_ascii_case(file_type))
+            .cloned()
+    }
+
+    /// シンタックスを登録する (登録済みのものより優先される)。
+    /// 開いているファイルに使うシンタックスが変わるときだけ塗り直す
+    pub fn register_syntax(&mut self, syntax: Rc<dyn SyntaxHighlight>) {
+        self.syntaxes.insert(0, syntax);
+        let detected = self.detected_syntax();
+        let changed = match (&detected, &self.document.syntax_highlight) {
+            (Some(detected), Some(current)) => !Rc::ptr_eq(detected, current),
+            (detected, current) => detected.is_some() || current.is_some(),
+        };
+        if changed {
+            self.document.syntax_highlight = detected;
+            self.rehighlight_all();
+        }
+    }
+
+    pub fn set_syntax(&mut self) -> io::Result<()> {
+        if let Some(name) = prompt!(self, "Syntax: {} (ESC to cancel)") {
+            if Output::select_syntax_by_name(&self.syntaxes, &name).is_some() {
+                self.syntax_override = Some(name);
+            } else {
+                self.syntax_override = None;
+                self.set_message(format!("Unknown syntax `{}`, using file extension", name));
+            }
+            self.detect_syntax();
+        }
+        Ok(())
+    }
+
+    /// 表示が内容とずれたときのために全行を塗り直す
+    pub fn redraw_syntax(&mut self) {
+        self.rehighlight_all();
+        self.set_message(format!(
+            "Rehighlighted {} lines",
+            self.document.editor_rows().number_of_row()
+        ));
+    }
+
+    /// シンタックスハイライトを切り替える。戻すときはシンタックスを選び直して全行をハイライトする
+    pub fn toggle_syntax_highlight(&mut self) {
+        self.syntax_disabled = !self.syntax_disabled;
+        self.detect_syntax();
+        self.set_message(
+            if self.syntax_disabled {
+                "Syntax highlighting off"
+            } else {
+                "Syntax highlighting on"
+            }
+            .into(),
+        );
+    }
+
+    /// 指定されたシンタックス、なければ拡張子、それもなければシバンからシンタックスを選び直して
+    /// 全行を再ハイライトする
+    fn detect_syntax(&mut self) {
+        if self.syntax_disabled {
+            self.document.syntax_highlight = None;
+            return;
+        }
+        self.document.syntax_highlight = self.detected_syntax();
+        self.rehighlight_all();
+    }
+
+    /// `detect_syntax` で使うシンタックスを選ぶ (ハイライトを無効にしている間は `None`)
+    fn detected_syntax(&self) -> Option<Rc<dyn SyntaxHighlight>> {
+        if self.syntax_disabled {
+            return None;
+        }
+        let by_name = self
+            .syntax_override
+            .as_ref()
+            .and_then(|name| Output::select_syntax_by_name(&self.syntaxes, name));
+        by_name.or_else(|| {
+            self.document
+                .editor_rows()
+                .filename
+                .as_ref()
+                .and_then(|path| path.extension())
+                .and_then(|ext| ext.to_str())
+                .and_then(|ext| Output::select_syntax(&self.syntaxes, ext))
+                .or_else(|| {
+                    let rows = self.document.editor_rows();
+                    (rows.number_of_row() > 0)
+                        .then(|| Output::shebang_file_type(rows.get_row(0)))
+                        .flatten()
+                        .and_then(|name| Output::select_syntax_by_name(&self.syntaxes, name))
+                })
+        })
+    }
+
+    /// すべての行の表示を作り直して塗り直す。検索の一致などの重ね塗りも消える
+    pub fn rehighlight_all(&mut self) {
+        self.search_index.overlay_row = None;
+        self.document.rehighlight_all();
+    }
+
+    /// プロンプトを閉じた後に前回の検索語で次の一致へ移動する。`reverse` なら逆向きに移動する。
+    /// 向きは前回の検索で最後に使った矢印キーの向き (`sticky_search_direction` が無効なら前方)。
+    /// カーソル位置より厳密に先 (後) の一致を探すので、途中で向きを変えても一致を飛ばさない
+    pub fn find_next(&mut self, reverse: bool) {
+        let Some(keyword) = self.search_index.last_keyword.clone() else {
+            self.set_message("No previous search".into());
+            return;
+        };
+        let mut direction = if self.config.sticky_search_direction {
+            self.search_index.last_direction
+        } else {
+            SearchDirection::Forward
+        };
+        if reverse {
+            direction = direction.reverse();
+        }
+        // 前回から内容が編集されているかもしれないので数え直す
+        self.search_index.keyword.clear();
+        self.count_matches(&keyword);
+        let matches = &self.search_index.matches;
+        if matches.is_empty() {
+            self.set_message(format!("Not found: {}", keyword));
+            return;
+        }
+
+        let (y, x) = self.cursor_position();
+        let current = if y < self.document.editor_rows().number_of_row() {
+            let row = self.document.editor_rows().get_editor_row(y);
+            (y, row.render_index(row.render_x(x)))
+        } else {
+            (y, 0)
+        };
+        let after = matches.partition_point(|&position| position <= current);
+        let before = matches.partition_point(|&position| position < current);
+        let (index, wrapped) = match direction {
+            SearchDirection::Forward if after < matches.len() => (after, false),
+            SearchDirection::Forward => (0, true),
+            SearchDirection::Backward if before > 0 => (before - 1, false),
+            SearchDirection::Backward => (matches.len() - 1, true),
+        };
+        let (match_y, match_x) = matches[index];
+        let total = matches.len();
+
+        self.record_jump();
+        self.clear_selection();
+        self.document.move_to_render_index(match_y, match_x);
+        self.search_index.current = Some(index);
+        self.set_message(format!(
+            "{} ({}/{}){}",
+            keyword,
+            index + 1,
+            total,
+            if wrapped { " [wrapped]" } else { "" }
+        ));
+    }
+
+    /// 検索プロンプトに一致の件数と現在位置を添える
+    fn find_hint(output: &Output) -> String {
+        let search_index = &output.search_index;
+        let count = match (search_index.keyword.is_empty(), search_index.current) {
+            (true, _) => String::new(),
+            (false, Some(current)) => format!(" ({}/{})", current + 1, search_index.matches.len()),
+            (false, None) => format!(" (0/{})", search_index.matches.len()),
+        };
+        format!("{} ({})", count, SearchAction::hint())
+    }
+
+    /// キーワードが変わったときだけ全行の一致を数え直す
+    fn count_matches(&mut self, keyword: &str) {
+        if self.search_index.keyword == keyword {
+            return;
+        }
+        self.search_index.keyword = keyword.to_string();
+        self.search_index.current = None;
+        self.search_index.matches = if keyword.is_empty() {
+            Vec::new()
+        } else {
+            self.document
+                .editor_rows()
+                .row_contents
+                .iter()
+                .enumerate()
+                .flat_map(|(y, row)| row.render.match_indices(keyword).map(move |(x, _)| (y, x)))
+                .collect()
+        };
+    }
+
+    /// 検索の一致を重ねた行の `overlay` を消す
+    fn clear_search_overlay(&mut self) {
+        if let Some(at) = self.search_index.overlay_row.take() {
+            if at < self.document.editor_rows().number_of_row() {
+                self.document
+                    .editor_rows_mut()
+                    .get_editor_row_mut(at)
+                    .overlay
+                    .clear();
+            }
+        }
     }
 
     fn find_callback(output: &mut Output, keyword: &str, key_code: KeyCode) {
-        if let Some((index, highlight)) = output.search_index.previous_highlight.take() {
-            output.editor_rows.get_editor_row_mut(index).highlight = highlight;
+        let action = SearchAction::from_key(key_code);
+        if action == Some(SearchAction::Help) {
+            output.overlay = match output.overlay {
+                Some(_) => None,
+                None => Some((SearchAction::help(), None)),
+            };
+            return;
         }
 
-        match key_code {
-            KeyCode::Esc | KeyCode::Enter => {
+        output.clear_search_overlay();
+
+        match action {
+            Some(SearchAction::Cancel | SearchAction::Accept) => {
+                output.overlay = None;
                 output.search_index.reset();
             }
             _ => {
                 output.search_index.y_direction = None;
                 output.search_index.x_direction = None;
-                match key_code {
-                    KeyCode::Down => {
+                match action {
+                    Some(SearchAction::NextLine) => {
                         output.search_index.y_direction = SearchDirection::Forward.into();
                     }
-                    KeyCode::Up => {
+                    Some(SearchAction::PreviousLine) => {
                         output.search_index.y_direction = SearchDirection::Backward.into();
                     }
-                    KeyCode::Left => {
+                    Some(SearchAction::PreviousMatch) => {
                         output.search_index.x_direction = SearchDirection::Backward.into();
                     }
-                    KeyCode::Right => {
+                    Some(SearchAction::NextMatch) => {
                         output.search_index.x_direction = SearchDirection::Forward.into();
                     }
                     _ => {}
                 }
+                if let Some(direction) = output
+                    .search_index
+                    .y_direction
+                    .or(output.search_index.x_direction)
+                {
+                    output.search_index.last_direction = direction;
+                }
+
+                output.count_matches(keyword);
+                let number_of_rows = output.document.editor_rows().number_of_row();
+                if number_of_rows == 0 {
+                    return;
+                }
 
-                for i in 0..output.editor_rows.number_of_row() {
+                // 検索開始位置の行から始めてファイル末尾で先頭に折り返し、最後に開始行の前半を探す
+                for i in 0..=number_of_rows {
                     let row_index = match output.search_index.y_direction.as_ref() {
                         None => {
                             if output.search_index.x_direction.is_none() {
-                                output.search_index.y_index = i;
+                                output.search_index.y_index =
+                                    (output.search_index.start_y + i) % number_of_rows;
                             }
                             output.search_index.y_index
                         }
@@ -312,19 +1839,27 @@ impl Output {
                         }
                     };
 
-                    if row_index > output.editor_rows.number_of_row() - 1 {
+                    if row_index > output.document.editor_rows().number_of_row() - 1 {
                         break;
                     }
 
-                    let row = output.editor_rows.get_editor_row_mut(row_index);
+                    let row = output
+                        .document
+                        .editor_rows_mut()
+                        .get_editor_row_mut(row_index);
                     let index = match output.search_index.x_direction.as_ref() {
-                        None => row.find(&keyword),
+                        None if i == 0 && output.search_index.y_direction.is_none() => {
+                            let start = row.render_index(output.search_index.start_x);
+                            row.render
+                                .get(start..)
+                                .and_then(|render| render.find(keyword))
+                                .map(|index| index + start)
+                        }
+                        None => row.find(keyword),
                         Some(dir) => {
                             let index = if matches!(dir, SearchDirection::Forward) {
                                 let start = row.len().min(output.search_index.x_index + 1);
-                                row.render[start..]
-                                    .find(&keyword)
-                                    .map(|index| index + start)
+                                row.render[start..].find(keyword).map(|index| index + start)
                             } else {
                                 row.render[..output.search_index.x_index].rfind(&keyword)
                             };
@@ -336,18 +1871,20 @@ impl Output {
                     };
 
                     if let Some(index) = index {
-                        output.search_index.previous_highlight =
-                            Some((row_index, row.highlight.clone()));
+                        row.overlay
+                            .push((index..index + keyword.len(), HighlightType::SearchMatch));
+                        output.search_index.overlay_row = Some(row_index);
 
-                        (index..index + keyword.len()).for_each(|index| {
-                            row.highlight[index] = HighlightType::SearchMatch;
-                        });
+                        // 重なった一致は数えていないので、直前の一致の番号を使う
+                        let ordinal = output
+                            .search_index
+                            .matches
+                            .partition_point(|&position| position <= (row_index, index));
+                        output.search_index.current = ordinal.checked_sub(1);
 
-                        output.cursor_controller.cursor_y = row_index;
                         output.search_index.y_index = row_index;
                         output.search_index.x_index = index;
-                        output.cursor_controller.cursor_x = row.get_row_content_x(index);
-                        output.cursor_controller.row_offset = output.editor_rows.number_of_row();
+                        output.document.move_to_render_index(row_index, index);
                         break;
                     }
                 }
@@ -355,39 +1892,311 @@ impl Output {
         }
     }
 
+    /// 本文の描画に使える列数。行番号とスクロールバーの分を除く
+    fn text_columns(&self) -> usize {
+        self.win_size
+            .0
+            .saturating_sub(self.gutter_width() + usize::from(self.config.scrollbar))
+            .max(1)
+    }
+
+    /// 端末の大きさからステータスバーとメッセージ欄を除いた大きさ。
+    /// 端末がどれだけ小さくても 1x1 は確保する
+    fn text_area((columns, rows): (u16, u16)) -> (usize, usize) {
+        (
+            usize::from(columns).max(1),
+            usize::from(rows).saturating_sub(2).max(1),
+        )
+    }
+
+    /// 端末の大きさが変わったときに呼ばれる
+    pub fn resize(&mut self, columns: u16, rows: u16) {
+        self.win_size = Output::text_area((columns, rows));
+        let cursor = &mut self.document.cursor_controller;
+        cursor.screen_rows = self.win_size.1;
+        cursor.screen_columns = self.win_size.0;
+    }
+
+    /// 行番号の列幅 (区切りの空白を含む)。どの表示方法でも行数の桁数に収まる
+    fn gutter_width(&self) -> usize {
+        if self.config.line_numbers == LineNumbers::Off {
+            return 0;
+        }
+        self.document
+            .editor_rows()
+            .number_of_row()
+            .max(1)
+            .to_string()
+            .len()
+            + 1
+    }
+
+    fn line_number(&self, file_row: usize) -> usize {
+        let cursor_y = self.document.cursor_controller.cursor_y;
+        match self.config.line_numbers {
+            LineNumbers::Absolute => file_row + 1,
+            LineNumbers::Hybrid if file_row == cursor_y => file_row + 1,
+            _ => file_row.abs_diff(cursor_y),
+        }
+    }
+
+    /// スクロールバーのつまみを描く画面上の行の範囲
+    fn scrollbar_thumb(&self) -> Range<usize> {
+        let screen_row = self.win_size.1;
+        let total = self.document.editor_rows().number_of_row();
+        if total <= screen_row {
+            return 0..screen_row;
+        }
+        let size = (screen_row * screen_row / total).max(1);
+        let start = (self.document.cursor_controller.row_offset * screen_row / total)
+            .min(screen_row - size);
+        start..start + size
+    }
+
     fn draw_rows(&mut self) {
         let screen_row = self.win_size.1;
-        let screen_column = self.win_size.0;
+        let screen_column = self.text_columns();
+        let thumb = self.scrollbar_thumb();
 
+        if let Some((lines, selected)) = self.overlay.take() {
+            self.draw_overlay(&lines, selected);
+            self.overlay = Some((lines, selected));
+            return;
+        }
+
+        let row_offset = self.document.cursor_controller.row_offset;
+        let number_of_rows = self.document.editor_rows().number_of_row();
+        self.document.refresh_brackets(
+            row_offset.min(number_of_rows)..(row_offset + screen_row).min(number_of_rows),
+        );
         for i in 0..screen_row {
-            let file_row = i + self.cursor_controller.row_offset;
-            if file_row >= self.editor_rows.number_of_row() {
+            let file_row = i + self.document.cursor_controller.row_offset;
+            if file_row >= self.document.editor_rows().number_of_row() {
                 // ファイルの行数以上の行の描画
-                if i == screen_row / 3 && self.editor_rows.number_of_row() == 0 {
+                if i == screen_row / 3 && self.document.editor_rows().number_of_row() == 0 {
                     self.draw_welcome();
-                } else {
+                } else if self.config.show_eof_marker
+                    && file_row == self.document.editor_rows().number_of_row()
+                    && file_row > 0
+                {
+                    // ファイル末尾の直後の行に印を付ける
+                    let marker = if self.config.show_tilde {
+                        "~ [EOF]"
+                    } else {
+                        "[EOF]"
+                    };
+                    self.editor_contents
+                        .push_str(Output::truncate_to_width(marker, screen_column));
+                } else if self.config.show_tilde {
                     self.editor_contents.push('~');
                 }
             } else {
                 // ファイルコンテンツの描画
-                let row = self.editor_rows.get_editor_row(file_row);
+                let gutter = self.gutter_width();
+                if gutter > 0 {
+                    let number = self.line_number(file_row);
+                    let color = if file_row == self.document.cursor_controller.cursor_y {
+                        Color::Reset
+                    } else {
+                        Color::DarkGrey
+                    };
+                    queue!(
+                        self.editor_contents,
+                        SetForegroundColor(color),
+                        Print(format!("{:>1$} ", number, gutter - 1)),
+                        SetForegroundColor(Color::Reset)
+                    )
+                    .unwrap();
+                }
+                let row = self.document.editor_rows().get_editor_row(file_row);
                 let render = &row.render;
-                let column_offset = self.cursor_controller.column_offset;
-                let len = row.len().saturating_sub(column_offset).min(screen_column);
-                let start = if len == 0 { 0 } else { column_offset };
-
-                self.syntax_highlight
-                    .as_ref()
-                    .map(|syntax_highlight| {
-                        syntax_highlight.color_row(
-                            &render[start..start + len],
-                            &row.highlight[start..start + len],
-                            &mut self.editor_contents,
+                let column_offset = self.document.cursor_controller.column_offset;
+                // 画面に入る文字だけを切り出す。左端で全角文字が途切れたらその分は空白で埋める
+                let (visible, padding) =
+                    row.visible_range(column_offset..column_offset + screen_column);
+                let (start, end) = (visible.start, visible.end);
+
+                // カーソル行は背景色を付ける (前景色の切り替えとは独立に設定する)
+                let current_line = self.config.highlight_current_line
+                    && file_row == self.document.cursor_controller.cursor_y;
+                if current_line {
+                    queue!(self.editor_contents, SetBackgroundColor(CURRENT_LINE_COLOR)).unwrap();
+                }
+                (0..padding).for_each(|_| self.editor_contents.push(' '));
+
+                // 選択範囲は反転表示し、行末の空白は赤く反転表示する
+                let selection = self.selection_in_row(file_row);
+                let (selection_from, selection_to) = selection
+                    .map_or((end, end), |(from, to, _)| {
+                        (from.clamp(start, end), to.clamp(start, end))
+                    });
+                let trailing = if self.config.highlight_trailing_whitespace {
+                    render.trim_end().len().clamp(start, end)
+                } else {
+                    end
+                };
+                // 上限の列を超えた部分は背景色を変える (画面上ではなくファイル上の列で数える)
+                let overlong = self
+                    .config
+                    .max_line_length
+                    .map_or(end, |limit| row.render_index(limit).clamp(start, end));
+                let mut bounds = vec![start, selection_from, selection_to, trailing, overlong, end];
+                bounds.extend(
+                    row.controls()
+                        .iter()
+                        .chain(row.tab_guides())
+                        .chain(row.overlay.iter().map(|(range, _)| range))
+                        .flat_map(|range| [range.start, range.end])
+                        .map(|at| at.clamp(start, end)),
+                );
+                bounds.sort_unstable();
+                for window in bounds.windows(2) {
+                    let (from, to) = (window[0], window[1]);
+                    if from == to {
+                        continue;
+                    }
+                    let selected = (selection_from..selection_to).contains(&from);
+                    // 制御文字は反転表示で区別する (選択中なら反転を戻す)
+                    if row.controls().iter().any(|range| range.contains(&from)) {
+                        queue!(
+                            self.editor_contents,
+                            SetAttribute(if selected {
+                                Attribute::NoReverse
+                            } else {
+                                Attribute::Reverse
+                            }),
+                            Print(&render[from..to]),
+                            SetAttribute(Attribute::NoReverse)
                         )
-                    })
-                    .unwrap_or_else(|| self.editor_contents.push_str(&render[start..start + len]));
+                        .unwrap();
+                        continue;
+                    }
+                    // タブの目印は目立たない色で描く
+                    if !selected && row.tab_guides().iter().any(|range| range.contains(&from)) {
+                        queue!(
+                            self.editor_contents,
+                            SetForegroundColor(Color::DarkGrey),
+                            Print(&render[from..to]),
+                            SetForegroundColor(Color::Reset)
+                        )
+                        .unwrap();
+                        continue;
+                    }
+                    if selected {
+                        queue!(self.editor_contents, SetAttribute(Attribute::Reverse)).unwrap();
+                    } else if from >= trailing {
+                        queue!(
+                            self.editor_contents,
+                            SetForegroundColor(Color::Red),
+                            SetAttribute(Attribute::Reverse),
+                            Print(&render[from..to]),
+                            SetAttribute(Attribute::NoReverse),
+                            SetForegroundColor(Color::Reset)
+                        )
+                        .unwrap();
+                        continue;
+                    }
+                    if from >= overlong {
+                        queue!(self.editor_contents, SetBackgroundColor(LONG_LINE_COLOR)).unwrap();
+                    }
+                    let overlay = row
+                        .overlay
+                        .iter()
+                        .find(|(range, _)| range.contains(&from))
+                        .map(|(_, highlight_type)| highlight_type);
+                    match (overlay, self.document.syntax_highlight.as_ref()) {
+                        (Some(highlight_type), syntax_highlight) => {
+                            let color = syntax_highlight.map_or(highlight_type.color(), |it| {
+                                it.syntax_color(highlight_type)
+                            });
+                            queue!(
+                                self.editor_contents,
+                                SetForegroundColor(color),
+                                Print(&render[from..to]),
+                                SetForegroundColor(Color::Reset)
+                            )
+                            .unwrap();
+                        }
+                        (None, Some(syntax_highlight)) => syntax_highlight.color_row(
+                            &render[from..to],
+                            &row.highlight[from..to],
+                            &mut self.editor_contents,
+                        ),
+                        (None, None) => self.editor_contents.push_str(&render[from..to]),
+                    }
+                    if from >= overlong {
+                        let background = if current_line {
+                            CURRENT_LINE_COLOR
+                        } else {
+                            Color::Reset
+                        };
+                        queue!(self.editor_contents, SetBackgroundColor(background)).unwrap();
+                    }
+                    if selected {
+                        queue!(self.editor_contents, SetAttribute(Attribute::NoReverse)).unwrap();
+                    }
+                }
+                // 改行まで選択されていれば行末に反転した空白を表示する
+                let mut width = padding + render[start..end].width();
+                if selection.is_some_and(|(_, _, past_end)| past_end)
+                    && row.display_width() >= column_offset
+                    && row.display_width() - column_offset < screen_column
+                {
+                    queue!(
+                        self.editor_contents,
+                        SetAttribute(Attribute::Reverse),
+                        Print(' '),
+                        SetAttribute(Attribute::NoReverse)
+                    )
+                    .unwrap();
+                    width += 1;
+                }
+                // 背景色を画面の右端まで伸ばすため、端末任せの消去ではなく空白で埋める
+                if current_line {
+                    (width..screen_column).for_each(|_| self.editor_contents.push(' '));
+                    queue!(self.editor_contents, SetBackgroundColor(Color::Reset)).unwrap();
+                }
             }
 
+            queue!(
+                self.editor_contents,
+                terminal::Clear(terminal::ClearType::UntilNewLine)
+            )
+            .unwrap();
+            if self.config.scrollbar {
+                queue!(
+                    self.editor_contents,
+                    crossterm::cursor::MoveToColumn(self.win_size.0 as u16 - 1),
+                    Print(if thumb.contains(&i) { '█' } else { '│' })
+                )
+                .unwrap();
+            }
+            self.editor_contents.push_str("\r\n");
+        }
+    }
+
+    /// 行の代わりに一覧を描画する。選択中の行が画面に収まるようにずらし、反転表示する
+    fn draw_overlay(&mut self, lines: &[String], selected: Option<usize>) {
+        let screen_row = self.win_size.1;
+        let offset = selected.map_or(0, |selected| (selected + 1).saturating_sub(screen_row));
+        for i in 0..screen_row {
+            match lines.get(i + offset) {
+                Some(line) => {
+                    let line = Output::truncate_to_width(line, self.win_size.0);
+                    if Some(i + offset) == selected {
+                        queue!(
+                            self.editor_contents,
+                            SetAttribute(Attribute::Reverse),
+                            Print(line),
+                            SetAttribute(Attribute::NoReverse)
+                        )
+                        .unwrap();
+                    } else {
+                        self.editor_contents.push_str(line);
+                    }
+                }
+                None => self.editor_contents.push('~'),
+            }
             queue!(
                 self.editor_contents,
                 terminal::Clear(terminal::ClearType::UntilNewLine)
@@ -398,13 +2207,13 @@ impl Output {
     }
 
     fn draw_welcome(&mut self) {
-        let screen_column = self.win_size.0;
+        let screen_column = self.text_columns();
         let mut welcome = format!("Pound Editor --- Version {}", "1.0.0");
         if welcome.len() > screen_column {
             welcome.truncate(screen_column);
         }
         let mut padding = (screen_column - welcome.len()) / 2;
-        if padding != 0 {
+        if padding != 0 && self.config.show_tilde {
             self.editor_contents.push('~');
             padding -= 1;
         }
@@ -418,26 +2227,67 @@ impl Output {
 
         let info = format!(
             "{} {} -- {} lines",
-            self.editor_rows.filename(),
-            if self.dirty > 0 { "(modified)" } else { "" },
-            self.editor_rows.number_of_row()
+            self.document.editor_rows().filename(),
+            if self.document.edits_since_save() > 0 {
+                "(modified)"
+            } else {
+                ""
+            },
+            self.document.editor_rows().number_of_row()
         );
-        let info_len = info.len().min(self.win_size.0);
+        let info = Output::truncate_to_width(&info, self.win_size.0);
+        let info_len = info.width();
 
-        let line_info = format!(
+        let mode = format!(
+            "{}{}{} | ",
+            if self.key_macro.is_recording() {
+                "REC | "
+            } else {
+                ""
+            },
+            if self.document.block().is_some() {
+                "BLOCK | "
+            } else if self.document.mark_active() {
+                "MARK | "
+            } else {
+                ""
+            },
+            if self.document.insert_mode {
+                "INS"
+            } else {
+                "OVR"
+            }
+        );
+        let position = format!(
             "{} | {}/{}",
-            self.syntax_highlight
+            self.document
+                .syntax_highlight
                 .as_ref()
                 .map(|highlight| highlight.file_type())
                 .unwrap_or("no ft"),
-            self.cursor_controller.cursor_y + 1,
-            self.editor_rows.number_of_row()
+            self.document.cursor_controller.cursor_y + 1,
+            self.document.editor_rows().number_of_row()
         );
+        // 文字コードと改行コードは幅が足りなければ改行コードだけ、それも無理なら省く
+        let rows = self.document.editor_rows();
+        let encoding = if rows.has_bom() { "utf-8-bom" } else { "utf-8" };
+        let line_ending = rows.line_ending.name();
+        let line_info = [
+            format!("{} | {} | ", encoding, line_ending),
+            format!("{} | ", line_ending),
+            String::new(),
+        ]
+        .into_iter()
+        .map(|format| format!("{}{}{}", mode, format, position))
+        .find(|it| info_len + it.width() < self.win_size.0)
+        .unwrap_or_else(|| format!("{}{}", mode, position));
+
+        let line_info_len = line_info.width();
 
-        self.editor_contents.push_str(&info[..info_len]);
+        self.editor_contents.push_str(info);
 
         for i in info_len..self.win_size.0 {
-            if self.win_size.0 - i == line_info.len() {
+            if self.win_size.0 - i == line_info_len {
                 self.editor_contents.push_str(&line_info);
                 break;
             } else {
@@ -450,6 +2300,18 @@ impl Output {
         self.editor_contents.push_str("\r\n");
     }
 
+    /// 表示幅が `width` に収まるよう文字境界で切り詰める
+    fn truncate_to_width(text: &str, width: usize) -> &str {
+        let mut current_width = 0;
+        for (i, c) in text.char_indices() {
+            current_width += c.width().unwrap_or(0);
+            if current_width > width {
+                return &text[..i];
+            }
+        }
+        text
+    }
+
     fn draw_message_bar(&mut self) {
         queue!(
             self.editor_contents,
@@ -458,28 +2320,37 @@ impl Output {
         .unwrap();
         if let Some(msg) = self.status_message.message() {
             self.editor_contents
-                .push_str(&msg[..msg.len().min(self.win_size.0)]);
+                .push_str(Output::truncate_to_width(msg, self.win_size.0));
         }
     }
 }
 
 pub struct EditorContents {
-    content: String,
+    content: Vec<u8>,
 }
 
 impl EditorContents {
     fn new() -> Self {
         Self {
-            content: String::new(),
+            content: Vec::new(),
         }
     }
 
     fn push(&mut self, ch: char) {
-        self.content.push(ch)
+        self.push_str(ch.encode_utf8(&mut [0; 4]))
     }
 
     fn push_str(&mut self, string: &str) {
-        self.content.push_str(string)
+        self.content.extend_from_slice(string.as_bytes())
+    }
+}
+
+/// 端末への出力先。テストでは端末を書き換えないよう捨てる
+pub fn screen() -> Box<dyn Write> {
+    if cfg!(test) {
+        Box::new(io::sink())
+    } else {
+        Box::new(stdout())
     }
 }
 
@@ -487,17 +2358,162 @@ impl io::Write for EditorContents {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         match std::str::from_utf8(buf) {
             Ok(s) => {
-                self.content.push_str(s);
+                self.push_str(s);
                 Ok(s.len())
             }
             Err(_) => Err(io::ErrorKind::WriteZero.into()),
         }
     }
 
+    /// 端末へ書き出せた分だけを取り除く。途中で失敗したときは残りを次の `flush` で書き出す
     fn flush(&mut self) -> io::Result<()> {
-        let out = write!(stdout(), "{}", self.content);
-        stdout().flush()?;
-        self.content.clear();
-        out
+        let mut out = screen();
+        let mut written = 0;
+        let result = loop {
+            if written == self.content.len() {
+                break out.flush();
+            }
+            match out.write(&self.content[written..]) {
+                Ok(0) => break Err(io::ErrorKind::WriteZero.into()),
+                Ok(len) => written += len,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => break Err(err),
+            }
+        };
+        self.content.drain(..written);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    fn output() -> Output {
+        Output::with_syntaxes(
+            Args::default(),
+            EditorConfig::default(),
+            Vec::new(),
+            Box::new(VecDeque::<KeyEvent>::new()),
+        )
+    }
+
+    struct TestSyntax {
+        extensions: Vec<String>,
+    }
+
+    impl SyntaxHighlight for TestSyntax {
+        fn extensions(&self) -> &[String] {
+            &self.extensions
+        }
+
+        fn file_type(&self) -> &str {
+            "Test"
+        }
+
+        fn comment_start(&self) -> &str {
+            "#"
+        }
+
+        fn keywords(&self) -> &[(Color, Vec<String>)] {
+            &[]
+        }
+    }
+
+    #[test]
+    fn registered_syntaxes_take_priority_over_the_builtin_one() {
+        let mut output = output();
+        output.document.set_filename(PathBuf::from("main.rs"));
+        output.detect_syntax();
+        let current = |output: &Output| {
+            let syntax = output.document.syntax_highlight.as_ref();
+            syntax.map(|it| it.file_type().to_string())
+        };
+        assert_eq!(current(&output).as_deref(), Some("rust"));
+
+        output.register_syntax(Rc::new(TestSyntax {
+            extensions: vec!["rs".into()],
+        }));
+        let selected = Output::select_syntax(&output.syntaxes, "rs").unwrap();
+        assert_eq!(selected.file_type(), "Test");
+        assert_eq!(current(&output).as_deref(), Some("Test"));
+
+        // 開いているファイルに関係しないシンタックスでは切り替わらない
+        output.register_syntax(Rc::new(TestSyntax {
+            extensions: vec!["txt".into()],
+        }));
+        assert!(Rc::ptr_eq(
+            output.document.syntax_highlight.as_ref().unwrap(),
+            &selected
+        ));
+    }
+
+    #[test]
+    fn text_area_keeps_at_least_one_cell() {
+        assert_eq!(Output::text_area((80, 0)), (80, 1));
+        assert_eq!(Output::text_area((80, 1)), (80, 1));
+        assert_eq!(Output::text_area((80, 2)), (80, 1));
+        assert_eq!(Output::text_area((80, 3)), (80, 1));
+        assert_eq!(Output::text_area((0, 24)), (1, 22));
+    }
+
+    #[test]
+    fn tiny_terminals_can_be_drawn() {
+        for (columns, rows) in [(80, 0), (80, 1), (80, 2), (0, 24), (0, 0)] {
+            let mut output = output();
+            output
+                .document
+                .insert_str("fn main() {\n\tprintln!(\"あ\");\n}");
+            output.resize(columns, rows);
+            output.document.cursor_controller.screen_columns = output.text_columns();
+            output.document.scroll();
+            output.draw_rows();
+            output.draw_status_bar();
+            output.draw_message_bar();
+            assert!(!output.editor_contents.content.is_empty());
+        }
+    }
+
+    #[test]
+    fn truncate_to_width_cuts_on_character_boundaries() {
+        assert_eq!(Output::truncate_to_width("a😀b", 0), "");
+        assert_eq!(Output::truncate_to_width("a😀b", 1), "a");
+        assert_eq!(Output::truncate_to_width("a😀b", 2), "a");
+        assert_eq!(Output::truncate_to_width("a😀b", 3), "a😀");
+        assert_eq!(Output::truncate_to_width("a😀b", 10), "a😀b");
+    }
+
+    #[test]
+    fn message_with_emoji_is_drawn_at_a_narrow_width() {
+        let mut output = output();
+        output.resize(4, 24);
+        output.set_message("😀😀😀 Save as: café".into());
+        output.editor_contents.content.clear();
+        output.draw_message_bar();
+        output.draw_status_bar();
+        let drawn = String::from_utf8(output.editor_contents.content.clone()).unwrap();
+        assert!(drawn.contains("😀😀"));
+        assert!(!drawn.contains("😀😀😀"));
+    }
+
+    #[test]
+    fn scrolled_rows_with_wide_characters_can_be_drawn() {
+        let mut output = output();
+        output.document.insert_str("aあいうえお\nxyz");
+        output.resize(5, 10);
+        for column_offset in 0..12 {
+            output.document.cursor_controller.column_offset = column_offset;
+            output.editor_contents.content.clear();
+            output.draw_rows();
+            let drawn = String::from_utf8(output.editor_contents.content.clone()).unwrap();
+            assert!(drawn.contains(match column_offset {
+                0 => "aあい",
+                1 => "あい\x1b",
+                2 => " いう",
+                3 => "いう\x1b",
+                _ => "",
+            }));
+        }
     }
 }
@@ -0,0 +1,21 @@
+/// 繰り返せる (Alt-. で再実行できる) 編集操作
+#[derive(Copy, Clone)]
+pub enum EditAction {
+    InsertChar(char),
+    InsertNewline,
+    InsertTab,
+    Outdent,
+    Backspace,
+    Delete,
+    JoinLines,
+    DeleteToEndOfLine,
+    DeleteToStartOfLine,
+    DeleteLine,
+    Paste,
+    /// 行単位でコピーしたテキストを現在の行の上に貼り付ける
+    PasteAbove,
+    Yank,
+    InsertTimestamp,
+    ToggleCase,
+    ReflowParagraph,
+}
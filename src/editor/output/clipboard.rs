@@ -0,0 +1,140 @@
+/// コピーしたテキストを保持する。`system` が有効なら OS のクリップボードを優先し、
+/// 使えないときは内部のクリップボードで代用する
+pub struct Clipboard {
+    internal: String,
+    /// 保持しているテキストが行単位でコピーしたもの (末尾に改行が付く)
+    linewise: bool,
+    system: bool,
+}
+
+impl Clipboard {
+    pub fn new(system: bool) -> Self {
+        Self {
+            internal: String::new(),
+            linewise: false,
+            system,
+        }
+    }
+
+    /// テキストを保持する。OS のクリップボードを使えなかったときはその理由を返す
+    pub fn copy(&mut self, text: String) -> Option<String> {
+        let note = if self.system {
+            system::copy(&text)
+                .err()
+                .map(|err| format!("system clipboard unavailable ({}), copied internally", err))
+        } else {
+            None
+        };
+        self.internal = text;
+        self.linewise = false;
+        note
+    }
+
+    /// 行単位でコピーする。貼り付けるときは行の途中ではなく新しい行として挿入する
+    pub fn copy_lines(&mut self, text: String) -> Option<String> {
+        let note = self.copy(text);
+        self.linewise = true;
+        note
+    }
+
+    /// `paste` で得たテキストが行単位でコピーしたものか
+    /// (OS のクリップボードが書き換えられていれば文字単位として扱う)
+    pub fn is_linewise(&self, text: &str) -> bool {
+        self.linewise && text == self.internal
+    }
+
+    /// 保持しているテキストと、OS のクリップボードを使えなかったときはその理由を返す
+    pub fn paste(&self) -> (String, Option<String>) {
+        if !self.system {
+            return (self.internal.clone(), None);
+        }
+        match system::paste() {
+            Ok(text) => (text.replace("\r\n", "\n"), None),
+            Err(err) => (
+                self.internal.clone(),
+                Some(format!(
+                    "system clipboard unavailable ({}), pasted internally",
+                    err
+                )),
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "system-clipboard")]
+mod system {
+    use std::env;
+    use std::io::{self, Write};
+    use std::process::{Command, Stdio};
+
+    /// 環境に合わせたコピー用とペースト用のコマンド
+    fn commands() -> (&'static [&'static str], &'static [&'static str]) {
+        if cfg!(target_os = "macos") {
+            (&["pbcopy"], &["pbpaste"])
+        } else if env::var_os("WAYLAND_DISPLAY").is_some() {
+            (&["wl-copy"], &["wl-paste", "--no-newline"])
+        } else {
+            (
+                &["xclip", "-selection", "clipboard"],
+                &["xclip", "-selection", "clipboard", "-o"],
+            )
+        }
+    }
+
+    pub fn copy(text: &str) -> io::Result<()> {
+        let (command, _) = commands();
+        let mut child = Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| io::Error::other("no stdin"))?
+            .write_all(text.as_bytes())?;
+        match child.wait()? {
+            status if status.success() => Ok(()),
+            status => Err(io::Error::other(format!(
+                "{} exited with {}",
+                command[0], status
+            ))),
+        }
+    }
+
+    pub fn paste() -> io::Result<String> {
+        let (_, command) = commands();
+        let output = Command::new(command[0])
+            .args(&command[1..])
+            .stderr(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "{} exited with {}",
+                command[0], output.status
+            )));
+        }
+        String::from_utf8(output.stdout).map_err(|err| io::Error::other(err.to_string()))
+    }
+}
+
+#[cfg(not(feature = "system-clipboard"))]
+mod system {
+    use std::io;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "built without the system-clipboard feature",
+        )
+    }
+
+    pub fn copy(_text: &str) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn paste() -> io::Result<String> {
+        Err(unsupported())
+    }
+}
@@ -2,9 +2,7 @@ use std::cmp::Ordering;
 
 use crossterm::event::KeyCode;
 
-use super::row::{EditorRows, Row};
-
-const TAB_STOP: usize = 8;
+use super::row::EditorRows;
 
 #[derive(Copy, Clone)]
 pub struct CursorController {
@@ -15,6 +13,11 @@ pub struct CursorController {
     pub row_offset: usize,
     pub column_offset: usize,
     pub render_x: usize,
+    /// 最終行の次の行 (文字を追加するための空の行) へ移動できる
+    pub past_last_row: bool,
+    /// 上下に動かすときに目指す表示上の列と、最後にその列を目指して置いた位置 `(x, y)`。
+    /// カーソルがその位置から動いていれば使わない
+    goal_column: Option<(usize, (usize, usize))>,
 }
 
 impl CursorController {
@@ -27,11 +30,29 @@ impl CursorController {
             row_offset: 0,
             column_offset: 0,
             render_x: 0,
+            past_last_row: true,
+            goal_column: None,
+        }
+    }
+
+    /// カーソルを動かせる最後の行
+    pub fn last_row(&self, number_of_rows: usize) -> usize {
+        if self.past_last_row {
+            number_of_rows
+        } else {
+            number_of_rows.saturating_sub(1)
         }
     }
 
     pub fn move_cursor(&mut self, direction: KeyCode, editor_rows: &EditorRows) {
         let number_of_rows = editor_rows.number_of_row();
+        let goal_column = match self.goal_column.take() {
+            Some((column, position)) if position == (self.cursor_x, self.cursor_y) => column,
+            _ if self.cursor_y < number_of_rows => editor_rows
+                .get_editor_row(self.cursor_y)
+                .render_x(self.cursor_x),
+            _ => 0,
+        };
 
         match direction {
             KeyCode::Up => {
@@ -42,19 +63,22 @@ impl CursorController {
                     self.cursor_x -= 1;
                 } else if self.cursor_y > 0 {
                     self.cursor_y -= 1;
-                    self.cursor_x = editor_rows.get_row(self.cursor_y).len();
+                    self.cursor_x = editor_rows.get_editor_row(self.cursor_y).char_len();
                 }
             }
             KeyCode::Down => {
-                if self.cursor_y < number_of_rows {
+                if self.cursor_y < self.last_row(number_of_rows) {
                     self.cursor_y += 1;
                 }
             }
             KeyCode::Right => {
                 if self.cursor_y < number_of_rows {
-                    match self.cursor_x.cmp(&editor_rows.get_row(self.cursor_y).len()) {
+                    match self
+                        .cursor_x
+                        .cmp(&editor_rows.get_editor_row(self.cursor_y).char_len())
+                    {
                         Ordering::Less => self.cursor_x += 1,
-                        Ordering::Equal => {
+                        Ordering::Equal if self.cursor_y < self.last_row(number_of_rows) => {
                             self.cursor_x = 0;
                             self.cursor_y += 1;
                         }
@@ -67,14 +91,54 @@ impl CursorController {
             }
             KeyCode::End => {
                 if self.cursor_y < number_of_rows {
-                    self.cursor_x = editor_rows.get_row(self.cursor_y).len();
+                    self.cursor_x = editor_rows.get_editor_row(self.cursor_y).char_len();
                 }
             }
             _ => unimplemented!(),
         }
 
-        let row_len = if self.cursor_y < number_of_rows {
-            editor_rows.get_row(self.cursor_y).len()
+        // 短い行を通り過ぎても元の列に戻れるよう、上下の移動では目指す列を引き継ぐ
+        if matches!(direction, KeyCode::Up | KeyCode::Down) {
+            self.cursor_x = if self.cursor_y < number_of_rows {
+                editor_rows
+                    .get_editor_row(self.cursor_y)
+                    .get_row_content_x(goal_column)
+            } else {
+                0
+            };
+            self.goal_column = Some((goal_column, (self.cursor_x, self.cursor_y)));
+        }
+        self.clamp_cursor_x(editor_rows);
+    }
+
+    /// 画面 1 つ分下へスクロールする。最終行を越えて画面の半分まではスクロールできるため、
+    /// ファイル末尾でもカーソルを画面の中ほどに置ける
+    pub fn scroll_past_end(&mut self, editor_rows: &EditorRows) {
+        let number_of_rows = editor_rows.number_of_row();
+        let max_row_offset = number_of_rows.saturating_sub(self.screen_rows / 2);
+        self.row_offset = (self.row_offset + self.screen_rows)
+            .min(max_row_offset)
+            .max(self.row_offset);
+        self.cursor_y = (self.cursor_y + self.screen_rows)
+            .min(self.last_row(number_of_rows))
+            .max(self.row_offset);
+        self.clamp_cursor_x(editor_rows);
+    }
+
+    /// `at` 行目が画面外にあれば画面の中ほどに来るようにスクロールする (画面内なら動かさない)。
+    /// ファイル末尾の行は最終行が画面の下端に来るところまでに留める
+    pub fn reveal_row(&mut self, at: usize, number_of_rows: usize) {
+        if (self.row_offset..self.row_offset + self.screen_rows).contains(&at) {
+            return;
+        }
+        self.row_offset = at
+            .saturating_sub(self.screen_rows / 2)
+            .min(number_of_rows.saturating_sub(self.screen_rows));
+    }
+
+    fn clamp_cursor_x(&mut self, editor_rows: &EditorRows) {
+        let row_len = if self.cursor_y < editor_rows.number_of_row() {
+            editor_rows.get_editor_row(self.cursor_y).char_len()
         } else {
             0
         };
@@ -84,7 +148,9 @@ impl CursorController {
     pub fn scroll(&mut self, editor_rows: &EditorRows) {
         self.render_x = 0;
         if self.cursor_y < editor_rows.number_of_row() {
-            self.render_x = self.get_render_x(editor_rows.get_editor_row(self.cursor_y));
+            self.render_x = editor_rows
+                .get_editor_row(self.cursor_y)
+                .render_x(self.cursor_x);
         }
 
         self.row_offset = self.row_offset.min(self.cursor_y);
@@ -97,14 +163,89 @@ impl CursorController {
             self.column_offset = self.render_x - self.screen_columns + 1;
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(text: &str) -> EditorRows {
+        let mut editor_rows = EditorRows::new(None);
+        editor_rows.set_contents(text);
+        editor_rows
+    }
 
-    fn get_render_x(&self, row: &Row) -> usize {
-        row.row_content[..self.cursor_x].chars().fold(0, |acc, c| {
-            if c == '\t' {
-                acc + (TAB_STOP - 1) - (acc % TAB_STOP) + 1
-            } else {
-                acc + 1
-            }
-        })
+    #[test]
+    fn end_lands_after_the_last_character_of_tab_and_wide_lines() {
+        let editor_rows = rows("\t\ta\t\nあいう");
+        let mut cursor = CursorController::new((80, 24));
+
+        cursor.move_cursor(KeyCode::End, &editor_rows);
+        cursor.scroll(&editor_rows);
+        assert_eq!(cursor.cursor_x, 4);
+        assert_eq!(cursor.render_x, 24);
+
+        cursor.cursor_y = 1;
+        cursor.move_cursor(KeyCode::End, &editor_rows);
+        cursor.scroll(&editor_rows);
+        assert_eq!(cursor.cursor_x, 3);
+        assert_eq!(cursor.render_x, 6);
+    }
+
+    #[test]
+    fn cursor_x_counts_characters_not_bytes() {
+        let editor_rows = rows("é\tü\nb");
+        let mut cursor = CursorController::new((80, 24));
+        cursor.cursor_y = 1;
+
+        // 前の行の末尾へ戻ると文字数の位置に来る
+        cursor.move_cursor(KeyCode::Left, &editor_rows);
+        assert_eq!((cursor.cursor_x, cursor.cursor_y), (3, 0));
+        cursor.move_cursor(KeyCode::Left, &editor_rows);
+        cursor.move_cursor(KeyCode::Left, &editor_rows);
+        assert_eq!(cursor.cursor_x, 1);
+        cursor.scroll(&editor_rows);
+        assert_eq!(cursor.render_x, 1);
+
+        cursor.move_cursor(KeyCode::Right, &editor_rows);
+        cursor.scroll(&editor_rows);
+        assert_eq!((cursor.cursor_x, cursor.render_x), (2, 8));
+        cursor.move_cursor(KeyCode::Right, &editor_rows);
+        cursor.move_cursor(KeyCode::Right, &editor_rows);
+        assert_eq!((cursor.cursor_x, cursor.cursor_y), (0, 1));
+    }
+
+    #[test]
+    fn vertical_moves_keep_the_goal_column_over_short_lines() {
+        let editor_rows = rows("long line here\nab\n\nanother long line\n\tx");
+        let mut cursor = CursorController::new((80, 24));
+        cursor.cursor_x = 10;
+        let mut down = |cursor: &mut CursorController| {
+            cursor.move_cursor(KeyCode::Down, &editor_rows);
+            (cursor.cursor_x, cursor.cursor_y)
+        };
+        assert_eq!(down(&mut cursor), (2, 1));
+        assert_eq!(down(&mut cursor), (0, 2));
+        assert_eq!(down(&mut cursor), (10, 3));
+        assert_eq!(down(&mut cursor), (2, 4));
+
+        cursor.move_cursor(KeyCode::Up, &editor_rows);
+        cursor.move_cursor(KeyCode::Up, &editor_rows);
+        cursor.move_cursor(KeyCode::Up, &editor_rows);
+        assert_eq!((cursor.cursor_x, cursor.cursor_y), (2, 1));
+
+        // 左右に動かすと目指す列はそこからやり直す
+        cursor.move_cursor(KeyCode::Left, &editor_rows);
+        assert_eq!(down(&mut cursor), (0, 2));
+        assert_eq!(down(&mut cursor), (1, 3));
+    }
+
+    #[test]
+    fn goal_column_counts_display_columns_across_tabs() {
+        let editor_rows = rows("12345678x\n\tx");
+        let mut cursor = CursorController::new((80, 24));
+        cursor.cursor_x = 8;
+        cursor.move_cursor(KeyCode::Down, &editor_rows);
+        assert_eq!((cursor.cursor_x, cursor.cursor_y), (1, 1));
     }
 }
@@ -0,0 +1,57 @@
+/// 共通部分の計算に使う表の大きさの上限。これを越える変更は共通部分なしとみなす
+const MAX_TABLE_SIZE: usize = 4_000_000;
+
+/// `old` から `new` への行単位の差分で、追加された行数と削除された行数を返す
+pub fn line_changes(old: &[&str], new: &[&str]) -> (usize, usize) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old, new) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+
+    let common = if old.len() * new.len() > MAX_TABLE_SIZE {
+        0
+    } else {
+        longest_common_subsequence(old, new)
+    };
+    (new.len() - common, old.len() - common)
+}
+
+fn longest_common_subsequence(old: &[&str], new: &[&str]) -> usize {
+    let mut previous = vec![0; new.len() + 1];
+    let mut current = vec![0; new.len() + 1];
+    for a in old {
+        for (j, b) in new.iter().enumerate() {
+            current[j + 1] = if a == b {
+                previous[j] + 1
+            } else {
+                previous[j + 1].max(current[j])
+            };
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[new.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_changes_counts_added_and_removed_lines() {
+        assert_eq!(line_changes(&["a", "b", "c"], &["a", "b", "c"]), (0, 0));
+        assert_eq!(line_changes(&["a", "b", "c"], &["a", "x", "c"]), (1, 1));
+        assert_eq!(line_changes(&["a", "c"], &["a", "b", "c", "d"]), (2, 0));
+        assert_eq!(line_changes(&["a", "b", "c"], &["c", "a"]), (1, 2));
+    }
+
+    #[test]
+    fn line_changes_from_nothing_adds_every_line() {
+        assert_eq!(line_changes(&[], &["a", "b"]), (2, 0));
+        assert_eq!(line_changes(&["a"], &[]), (0, 1));
+    }
+}
@@ -0,0 +1,1459 @@
+use std::{io, ops::Range, path::PathBuf, rc::Rc, time::SystemTime};
+
+use crossterm::event::KeyCode;
+use unicode_width::UnicodeWidthStr;
+
+use super::cursor::CursorController;
+use super::highlight::{self, SyntaxHighlight};
+use super::row::{self, EditorRows, LineEnding, Row, SaveOptions};
+
+/// 文字単位の `(x, y)`
+pub type Position = (usize, usize);
+
+/// 入力を整える設定
+#[derive(Clone, Copy)]
+pub struct EditOptions {
+    /// Tab で空白を入れ、インデントの中の Backspace で前のタブ位置まで消す
+    pub soft_tabs: bool,
+    /// インデント 1 段の幅
+    pub indent_size: usize,
+    /// 改行で前の行のインデントを引き継ぎ、閉じ括弧でインデントを 1 段浅くする
+    pub auto_indent: bool,
+}
+
+impl Default for EditOptions {
+    fn default() -> Self {
+        Self {
+            soft_tabs: false,
+            indent_size: 4,
+            auto_indent: false,
+        }
+    }
+}
+
+/// 編集中のバッファ。行・カーソル・選択範囲・変更状態を持ち、端末に依存しない編集操作を提供する
+pub struct Document {
+    editor_rows: EditorRows,
+    pub cursor_controller: CursorController,
+    /// 変更のたびに増える (保存すると 0 に戻る)
+    dirty: u64,
+    pub syntax_highlight: Option<Rc<dyn SyntaxHighlight>>,
+    /// 塗ったときの行頭の括弧の深さが正しいとわかっている先頭からの行数
+    brackets_checked: usize,
+    /// `false` なら上書きモード
+    pub insert_mode: bool,
+    /// 選択範囲の起点 (文字単位の `(x, y)`)。カーソルとの間が選択範囲になる
+    pub selection_anchor: Option<Position>,
+    /// Ctrl-Space で置いたマーク。起点と一致する間は通常の移動でも選択範囲を広げる
+    mark: Option<Position>,
+    /// 矩形選択中か (起点とカーソルの表示上の列の間を各行で選択する)
+    block_selection: bool,
+    /// 最後に保存した時刻
+    last_saved: Option<SystemTime>,
+}
+
+impl Document {
+    pub fn new(editor_rows: EditorRows, win_size: (usize, usize)) -> Self {
+        Self {
+            editor_rows,
+            cursor_controller: CursorController::new(win_size),
+            dirty: 0,
+            brackets_checked: 0,
+            syntax_highlight: None,
+            insert_mode: true,
+            selection_anchor: None,
+            mark: None,
+            block_selection: false,
+            last_saved: None,
+        }
+    }
+
+    pub fn editor_rows(&self) -> &EditorRows {
+        &self.editor_rows
+    }
+
+    /// 行の表示 (ハイライトや検索の一致の重ね塗り) だけを変えるときに使う。
+    /// 内容を変えるときは変更として数える編集操作を使う
+    pub fn editor_rows_mut(&mut self) -> &mut EditorRows {
+        &mut self.editor_rows
+    }
+
+    /// 保存していない変更があるか (スクラッチバッファは常に `false`)
+    pub fn is_dirty(&self) -> bool {
+        self.dirty > 0 && !self.editor_rows.scratch
+    }
+
+    /// 最後に保存して (開いて) からの編集回数
+    pub fn edits_since_save(&self) -> u64 {
+        self.dirty
+    }
+
+    pub fn last_saved(&self) -> Option<SystemTime> {
+        self.last_saved
+    }
+
+    /// 編集した行のハイライトを更新する
+    fn update_syntax(&mut self, at: usize) {
+        if let Some(it) = self.syntax_highlight.as_ref() {
+            it.update_syntax(at, &mut self.editor_rows.row_contents);
+        }
+        // 編集した行の括弧が変わると、後ろの行の行頭の深さがずれる
+        self.brackets_checked = self.brackets_checked.min(at + 1);
+    }
+
+    /// `rows` の行の括弧の色を、編集で変わった括弧の深さに合わせる
+    pub fn refresh_brackets(&mut self, rows: Range<usize>) {
+        if let Some(it) = self.syntax_highlight.as_ref() {
+            self.brackets_checked = it.refresh_brackets(
+                rows,
+                self.brackets_checked,
+                &mut self.editor_rows.row_contents,
+            );
+        }
+    }
+
+    /// すべての行の表示を作り直して塗り直す
+    pub fn rehighlight_all(&mut self) {
+        for row in self.editor_rows.row_contents.iter_mut() {
+            EditorRows::render_row(row);
+            row.highlight.clear();
+        }
+        if let Some(highlight) = self.syntax_highlight.as_ref() {
+            for i in 0..self.editor_rows.number_of_row() {
+                highlight.update_syntax(i, &mut self.editor_rows.row_contents);
+            }
+        }
+        // 先頭から順に塗ったので、どの行も前の行の続きの深さで塗ってある
+        self.brackets_checked = self.editor_rows.number_of_row();
+    }
+
+    /// 名前を付けて保存するときのファイル名を設定する (スクラッチバッファではなくなる)
+    pub fn set_filename(&mut self, filename: PathBuf) {
+        self.editor_rows.filename = Some(filename);
+        self.editor_rows.scratch = false;
+    }
+
+    /// ディスク上のファイルを読み直す。読み直した内容は変更として数えない
+    pub fn reload(&mut self) -> io::Result<()> {
+        self.editor_rows.reload()?;
+        self.dirty = 0;
+        self.selection_anchor = None;
+        self.clamp_cursor();
+        Ok(())
+    }
+
+    /// 復旧用の控えの内容に置き換える (保存していない変更として数える)
+    pub fn restore(&mut self, contents: &str, cursor: Position) {
+        self.editor_rows.set_contents(contents);
+        self.cursor_controller.cursor_x = cursor.0;
+        self.cursor_controller.cursor_y = cursor.1;
+        self.clamp_cursor();
+        self.rehighlight_all();
+        self.dirty += 1;
+    }
+
+    /// 保存時の改行コードを設定する。改行コードが変わるか混在が解消されたら変更として数え `true` を返す
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) -> bool {
+        let rows = &mut self.editor_rows;
+        if line_ending == rows.line_ending && !rows.mixed_line_endings {
+            return false;
+        }
+        rows.line_ending = line_ending;
+        rows.mixed_line_endings = false;
+        self.dirty += 1;
+        true
+    }
+
+    /// カーソルを行の中 (最終行の次の行なら行頭) に収める
+    pub fn clamp_cursor(&mut self) {
+        let rows = self.editor_rows.number_of_row();
+        let cursor = &mut self.cursor_controller;
+        cursor.cursor_y = cursor.cursor_y.min(rows);
+        cursor.cursor_x = if cursor.cursor_y < rows {
+            cursor
+                .cursor_x
+                .min(self.editor_rows.get_editor_row(cursor.cursor_y).char_len())
+        } else {
+            0
+        };
+    }
+
+    pub fn move_cursor(&mut self, direction: KeyCode) {
+        self.cursor_controller
+            .move_cursor(direction, &self.editor_rows);
+    }
+
+    /// カーソルが画面に収まるようにスクロールする
+    pub fn scroll(&mut self) {
+        self.cursor_controller.scroll(&self.editor_rows);
+    }
+
+    pub fn scroll_past_end(&mut self) {
+        self.cursor_controller.scroll_past_end(&self.editor_rows);
+    }
+
+    /// `y` 行目の `render` の `index` バイト目 (検索の一致など) にカーソルを移し、画面外なら見える位置までスクロールする
+    pub fn move_to_render_index(&mut self, y: usize, index: usize) {
+        let row = self.editor_rows.get_editor_row(y);
+        self.cursor_controller.cursor_x = row.get_row_content_x(row.render[..index].width());
+        self.cursor_controller.cursor_y = y;
+        self.cursor_controller
+            .reveal_row(y, self.editor_rows.number_of_row());
+    }
+
+    fn cursor(&self) -> Position {
+        (
+            self.cursor_controller.cursor_x,
+            self.cursor_controller.cursor_y,
+        )
+    }
+
+    /// 選択範囲を広げながらカーソルを動かす
+    pub fn select_move(&mut self, direction: KeyCode) {
+        let cursor = self.cursor();
+        self.selection_anchor.get_or_insert(cursor);
+        self.cursor_controller
+            .move_cursor(direction, &self.editor_rows);
+    }
+
+    /// バッファ全体を選択し、カーソルを最終行の末尾に置く
+    pub fn select_all(&mut self) {
+        let number_of_rows = self.editor_rows.number_of_row();
+        if number_of_rows == 0 {
+            return;
+        }
+        self.selection_anchor = Some((0, 0));
+        self.cursor_controller.cursor_y = number_of_rows - 1;
+        self.cursor_controller.cursor_x = self
+            .editor_rows
+            .get_editor_row(number_of_rows - 1)
+            .char_len();
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection_anchor = None;
+        self.mark = None;
+        self.block_selection = false;
+    }
+
+    /// 現在位置にマークを置き、以降の移動で選択範囲を広げる
+    pub fn set_mark(&mut self) {
+        self.selection_anchor = Some(self.cursor());
+        self.mark = self.selection_anchor;
+    }
+
+    /// マークが有効か (編集やクリックで起点が変わったら無効になる)
+    pub fn mark_active(&self) -> bool {
+        self.mark.is_some() && self.mark == self.selection_anchor
+    }
+
+    pub fn is_block_selection(&self) -> bool {
+        self.block_selection
+    }
+
+    /// 矩形選択を切り替える。選択範囲が無ければ現在位置を起点にする
+    pub fn toggle_block_selection(&mut self) {
+        if self.block_selection {
+            self.clear_selection();
+            return;
+        }
+        let cursor = self.cursor();
+        self.selection_anchor.get_or_insert(cursor);
+        self.block_selection = true;
+    }
+
+    /// 矩形選択の行の範囲と表示上の列の範囲
+    pub fn block(&self) -> Option<(Range<usize>, Range<usize>)> {
+        let number_of_rows = self.editor_rows.number_of_row();
+        let anchor = self
+            .selection_anchor
+            .filter(|_| self.block_selection && number_of_rows > 0)?;
+        let column = |(x, y): Position| {
+            let y = y.min(number_of_rows - 1);
+            (self.editor_rows.get_editor_row(y).render_x(x), y)
+        };
+        let (anchor, cursor) = (column(anchor), column(self.cursor()));
+        Some((
+            anchor.1.min(cursor.1)..anchor.1.max(cursor.1) + 1,
+            anchor.0.min(cursor.0)..anchor.0.max(cursor.0),
+        ))
+    }
+
+    /// 矩形選択の各行の選択範囲に `edit` を適用する。列まで届かない行は飛ばす。
+    /// `edit` は編集後の列の位置 (文字単位) を返し、起点とカーソルもその位置に移す
+    fn edit_block(&mut self, edit: impl Fn(&mut Row, Range<usize>) -> Option<usize>) {
+        let Some((rows, columns)) = self.block() else {
+            return;
+        };
+        let mut edited = false;
+        for y in rows {
+            let row = self.editor_rows.get_editor_row_mut(y);
+            let Some(x) = row
+                .column_range(&columns)
+                .and_then(|range| edit(row, range))
+            else {
+                continue;
+            };
+            edited = true;
+            if y == self.cursor_controller.cursor_y {
+                self.cursor_controller.cursor_x = x;
+            }
+            if let Some(anchor) = self.selection_anchor.as_mut().filter(|it| it.1 == y) {
+                anchor.0 = x;
+            }
+            self.update_syntax(y);
+        }
+        if edited {
+            self.dirty += 1;
+        }
+    }
+
+    /// 矩形選択の範囲を削除する。範囲の幅が無ければ `backward` なら列の前、そうでなければ列の文字を削除する
+    fn delete_block(&mut self, backward: bool) {
+        let Some((_, columns)) = self.block() else {
+            return;
+        };
+        if columns.is_empty() {
+            self.edit_block(|row, range| {
+                let at = if backward {
+                    range.start.checked_sub(1)?
+                } else {
+                    range.start
+                };
+                (at < row.char_len()).then(|| {
+                    row.delete_char(at);
+                    at
+                })
+            });
+        } else {
+            self.edit_block(|row, range| {
+                let start = range.start;
+                row.delete_chars(range);
+                Some(start)
+            });
+        }
+    }
+
+    /// 矩形選択の範囲を `ch` に置き換える (幅が無ければ各行の列に挿入する)
+    fn insert_block(&mut self, ch: char) {
+        self.edit_block(|row, range| {
+            let start = range.start;
+            row.delete_chars(range);
+            row.insert_char(start, ch);
+            Some(start + 1)
+        });
+    }
+
+    /// 矩形選択の各行の範囲を改行で繋げた文字列
+    pub fn block_text(&self) -> Option<String> {
+        let (rows, columns) = self.block()?;
+        Some(
+            rows.map(|y| {
+                let row = self.editor_rows.get_editor_row(y);
+                row.column_range(&columns)
+                    .map(|range| {
+                        &row.row_content[row.byte_index(range.start)..row.byte_index(range.end)]
+                    })
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<&str>>()
+            .join("\n"),
+        )
+    }
+
+    /// カーソル位置の単語の範囲。空白の上なら続く空白を、区切りの記号ならその 1 文字を返す
+    fn word_at_cursor(&self) -> Option<(Position, Position)> {
+        let (x, y) = self.cursor();
+        if y >= self.editor_rows.number_of_row() {
+            return None;
+        }
+        let chars: Vec<char> = self.editor_rows.get_row(y).chars().collect();
+        if chars.is_empty() {
+            return None;
+        }
+        let class = |c: char| {
+            if c.is_whitespace() {
+                0
+            } else if highlight::is_separator(c) {
+                1
+            } else {
+                2
+            }
+        };
+        let at = x.min(chars.len() - 1);
+        let kind = class(chars[at]);
+        let (mut start, mut end) = (at, at + 1);
+        if kind != 1 {
+            while start > 0 && class(chars[start - 1]) == kind {
+                start -= 1;
+            }
+            while end < chars.len() && class(chars[end]) == kind {
+                end += 1;
+            }
+        }
+        Some(((start, y), (end, y)))
+    }
+
+    /// カーソル位置の単語を選択する
+    pub fn select_word(&mut self) {
+        if let Some((start, end)) = self.word_at_cursor() {
+            self.selection_anchor = Some(start);
+            self.cursor_controller.cursor_x = end.0;
+        }
+    }
+
+    /// カーソルのある行を改行まで選択する (最終行は行末まで)
+    pub fn select_line(&mut self) {
+        let number_of_rows = self.editor_rows.number_of_row();
+        let cursor = &mut self.cursor_controller;
+        if cursor.cursor_y >= number_of_rows {
+            return;
+        }
+        self.selection_anchor = Some((0, cursor.cursor_y));
+        if cursor.cursor_y + 1 < number_of_rows {
+            cursor.cursor_y += 1;
+            cursor.cursor_x = 0;
+        } else {
+            cursor.cursor_x = self.editor_rows.get_editor_row(cursor.cursor_y).char_len();
+        }
+    }
+
+    /// 選択範囲の開始位置と終了位置 (終了位置は含まない) を文書順に返す
+    pub fn selection(&self) -> Option<(Position, Position)> {
+        let number_of_rows = self.editor_rows.number_of_row();
+        // 最終行の次の行は最終行の末尾として扱う
+        let clamp = |(x, y): Position| {
+            if y < number_of_rows {
+                (x, y)
+            } else {
+                let y = number_of_rows - 1;
+                (self.editor_rows.get_editor_row(y).char_len(), y)
+            }
+        };
+        let anchor = self.selection_anchor.filter(|_| number_of_rows > 0)?;
+        let cursor = self.cursor();
+        let (start, end) = if (anchor.1, anchor.0) <= (cursor.1, cursor.0) {
+            (clamp(anchor), clamp(cursor))
+        } else {
+            (clamp(cursor), clamp(anchor))
+        };
+        (start != end).then_some((start, end))
+    }
+
+    /// 選択範囲があれば削除して `true` を返す。選択は常に解除される
+    pub fn delete_selection(&mut self) -> bool {
+        if let Some((_, columns)) = self.block() {
+            self.delete_block(false);
+            self.clear_selection();
+            return !columns.is_empty();
+        }
+        let selection = self.selection();
+        self.selection_anchor = None;
+        let Some((start, end)) = selection else {
+            return false;
+        };
+        self.editor_rows.delete_range(start, end);
+        self.update_syntax(start.1);
+        self.cursor_controller.cursor_x = start.0;
+        self.cursor_controller.cursor_y = start.1;
+        self.dirty += 1;
+        true
+    }
+
+    /// `start` から `end` の手前までを `text` に置き換えて 1 回の編集として数え、
+    /// カーソルを置き換えたテキストの後ろに移す。置き換えたテキストの末尾の位置を返す
+    pub fn replace_range(&mut self, start: Position, end: Position, text: &str) -> Position {
+        if start != end {
+            self.editor_rows.delete_range(start, end);
+        }
+        let end = self.editor_rows.insert_text(start, text);
+        for at in start.1..=end.1 {
+            self.update_syntax(at);
+        }
+        self.cursor_controller.cursor_x = end.0;
+        self.cursor_controller.cursor_y = end.1;
+        self.dirty += 1;
+        end
+    }
+
+    /// 選択範囲を置き換えてカーソル位置にテキストを 1 回の編集として挿入し、
+    /// カーソルを挿入したテキストの後ろに移す。挿入した範囲の先頭と末尾を返す
+    pub fn insert_str(&mut self, text: &str) -> (Position, Position) {
+        self.delete_selection();
+        let start = self.cursor();
+        (start, self.replace_range(start, start, text))
+    }
+
+    /// 行単位でコピーしたテキストを現在の行の下 (`above` なら上) に新しい行として挿入し、
+    /// カーソルを挿入した最初の行の先頭に置く
+    pub fn insert_lines(&mut self, text: &str, above: bool) {
+        self.clear_selection();
+        let y = self.cursor_controller.cursor_y;
+        let number_of_rows = self.editor_rows.number_of_row();
+        let at = if above || y >= number_of_rows {
+            y.min(number_of_rows)
+        } else {
+            y + 1
+        };
+        let lines: Vec<&str> = text
+            .strip_suffix('\n')
+            .unwrap_or(text)
+            .split('\n')
+            .collect();
+        for (i, line) in lines.iter().enumerate() {
+            self.editor_rows.insert_row(at + i, line.to_string());
+        }
+        for y in at..at + lines.len() {
+            self.update_syntax(y);
+        }
+        self.cursor_controller.cursor_x = 0;
+        self.cursor_controller.cursor_y = at;
+        self.dirty += 1;
+    }
+
+    /// カーソル位置に `ch` を入力する。矩形選択中は各行の範囲を置き換え、選択範囲があれば置き換える
+    pub fn insert_char(&mut self, ch: char, options: EditOptions) {
+        if self.block().is_some() {
+            self.insert_block(ch);
+            return;
+        }
+        self.delete_selection();
+        // 行頭の空白の後に閉じ括弧を打ったら、対応する開き括弧の行にそろえるよう 1 段浅くする
+        if options.auto_indent && matches!(ch, '}' | ')' | ']') && self.in_indent() {
+            self.outdent(options.indent_size);
+        }
+        self.put_char(ch);
+    }
+
+    /// カーソルが行頭の空白の中 (空白の直後) にあるか
+    fn in_indent(&self) -> bool {
+        let (x, y) = self.cursor();
+        x > 0
+            && y < self.editor_rows.number_of_row()
+            && self
+                .editor_rows
+                .get_row(y)
+                .chars()
+                .take(x)
+                .all(|c| c == ' ' || c == '\t')
+    }
+
+    /// ソフトタブなら次のインデント位置まで空白を、そうでなければタブを入力する
+    pub fn insert_tab(&mut self, options: EditOptions) {
+        self.delete_selection();
+        if !options.soft_tabs {
+            self.insert_char('\t', options);
+            return;
+        }
+        // 既存のタブも含めた表示上の列から次のインデント位置までを空白で埋める
+        let (x, y) = self.cursor();
+        let render_x = if y < self.editor_rows.number_of_row() {
+            self.editor_rows.get_editor_row(y).render_x(x)
+        } else {
+            0
+        };
+        let spaces = options.indent_size - render_x % options.indent_size;
+        (0..spaces).for_each(|_| self.put_char(' '));
+    }
+
+    /// 改行する。`auto_indent` なら前の行のインデントを引き継ぎ、開き括弧の後なら 1 段深くする
+    pub fn insert_newline(&mut self, options: EditOptions) {
+        self.delete_selection();
+        if !options.auto_indent {
+            self.break_line();
+            return;
+        }
+        let (x, y) = self.cursor();
+        let mut indent = String::new();
+        if y < self.editor_rows.number_of_row() {
+            let before: String = self.editor_rows.get_row(y).chars().take(x).collect();
+            indent = before
+                .chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .collect();
+            if before.trim_end().ends_with(['{', '(', '[']) {
+                if options.soft_tabs {
+                    indent.push_str(&" ".repeat(options.indent_size));
+                } else {
+                    indent.push('\t');
+                }
+            }
+        }
+        self.break_line();
+        indent.chars().for_each(|c| self.put_char(c));
+    }
+
+    /// カーソルの前の文字 (選択範囲があればそれ) を削除する。
+    /// ソフトタブならインデントの中では前のタブ位置まで空白をまとめて消す
+    pub fn backspace(&mut self, options: EditOptions) {
+        if self.block().is_some() {
+            self.delete_block(true);
+            return;
+        }
+        if self.delete_selection() {
+            return;
+        }
+        let (cursor_x, cursor_y) = self.cursor();
+        // インデント (タブと空白の混在も含む) の中で空白の直後にいれば、前のタブ位置まで空白を消す
+        let in_indent =
+            options.soft_tabs && cursor_x > 0 && cursor_y < self.editor_rows.number_of_row() && {
+                let indent: Vec<char> = self
+                    .editor_rows
+                    .get_row(cursor_y)
+                    .chars()
+                    .take(cursor_x)
+                    .collect();
+                indent.iter().all(|&c| c == ' ' || c == '\t') && indent.last() == Some(&' ')
+            };
+        if !in_indent {
+            self.delete_char();
+            return;
+        }
+
+        let row = self.editor_rows.get_editor_row(cursor_y);
+        let indent_size = options.indent_size;
+        let tab_stop = (row.render_x(cursor_x) - 1) / indent_size * indent_size;
+        let mut start = cursor_x;
+        while start > 0
+            && row.row_content.as_bytes()[start - 1] == b' '
+            && row.render_x(start - 1) >= tab_stop
+        {
+            start -= 1;
+        }
+        let row = self.editor_rows.get_editor_row_mut(cursor_y);
+        row.row_content.drain(start..cursor_x);
+        EditorRows::render_row(row);
+        self.update_syntax(cursor_y);
+        self.cursor_controller.cursor_x = start;
+        self.dirty += 1;
+    }
+
+    /// カーソル位置の文字 (選択範囲があればそれ) を削除する。行末では次の行を連結する
+    pub fn delete(&mut self) {
+        if self.block().is_some() {
+            self.delete_block(false);
+            return;
+        }
+        if self.delete_selection() {
+            return;
+        }
+        self.cursor_controller
+            .move_cursor(KeyCode::Right, &self.editor_rows);
+        self.delete_char();
+    }
+
+    /// カーソルから行末までを削除して返す。行末では次の行を連結して改行を返す
+    pub fn delete_to_end_of_line(&mut self) -> Option<String> {
+        self.selection_anchor = None;
+        let (x, y) = self.cursor();
+        let number_of_rows = self.editor_rows.number_of_row();
+        if y >= number_of_rows {
+            return None;
+        }
+        let row = self.editor_rows.get_editor_row_mut(y);
+        let killed = if x < row.char_len() {
+            let killed = row.row_content.split_off(row.byte_index(x));
+            EditorRows::render_row(row);
+            killed
+        } else if y + 1 < number_of_rows {
+            self.editor_rows.join_adjacent_rows(y + 1);
+            "\n".to_string()
+        } else {
+            return None;
+        };
+        self.update_syntax(y);
+        self.dirty += 1;
+        Some(killed)
+    }
+
+    /// 行頭からカーソルまでを削除して返す。`to_indent` ならインデントの後からカーソルまでを削除し、
+    /// インデントの中にいるときはインデントごと削除する
+    pub fn delete_to_start_of_line(&mut self, to_indent: bool) -> Option<String> {
+        self.selection_anchor = None;
+        let (x, y) = self.cursor();
+        if y >= self.editor_rows.number_of_row() {
+            return None;
+        }
+        let row = self.editor_rows.get_editor_row_mut(y);
+        let indent = row
+            .row_content
+            .chars()
+            .take_while(|c| c.is_whitespace())
+            .count();
+        let start = if to_indent && indent < x { indent } else { 0 };
+        if start == x {
+            return None;
+        }
+        let killed = row
+            .row_content
+            .drain(row.byte_index(start)..row.byte_index(x))
+            .collect();
+        EditorRows::render_row(row);
+        self.update_syntax(y);
+        self.cursor_controller.cursor_x = start;
+        self.dirty += 1;
+        Some(killed)
+    }
+
+    /// 選択範囲 (無ければカーソル位置の単語) を大文字→小文字→先頭だけ大文字の順に切り替える
+    pub fn toggle_case(&mut self) {
+        let selection = self.selection();
+        if selection.is_none() {
+            self.selection_anchor = None;
+        }
+        let Some((start, end)) = selection.or_else(|| self.word_at_cursor()) else {
+            return;
+        };
+        let text = self.editor_rows.text_range(start, end);
+        let converted = cycle_case(&text);
+        if converted == text {
+            return;
+        }
+        let cursor_x = self.cursor_controller.cursor_x;
+        let end = self.replace_range(start, end, &converted);
+        // 選択範囲は変換後のテキストを選択し直し、単語ならカーソルを単語の中に留める
+        if selection.is_some() {
+            self.selection_anchor = Some(start);
+        } else {
+            self.cursor_controller.cursor_x = cursor_x.min(end.0);
+        }
+    }
+
+    /// `first` 行目から `last` 行目までを並べ替え、カーソルを先頭の行の行頭に置く
+    pub fn sort_lines(&mut self, first: usize, last: usize, ignore_case: bool, reverse: bool) {
+        let rows = &mut self.editor_rows.row_contents[first..=last];
+        if ignore_case {
+            rows.sort_by_cached_key(|row| row.row_content.to_lowercase());
+        } else {
+            rows.sort_by(|a, b| a.row_content.cmp(&b.row_content));
+        }
+        if reverse {
+            rows.reverse();
+        }
+        for at in first..=last {
+            self.update_syntax(at);
+        }
+        self.selection_anchor = None;
+        self.cursor_controller.cursor_x = 0;
+        self.cursor_controller.cursor_y = first;
+        self.dirty += 1;
+    }
+
+    /// カーソル位置に 1 文字書き込む (上書きモードなら行末以外では置き換える)
+    fn put_char(&mut self, ch: char) {
+        let cursor = &mut self.cursor_controller;
+        if cursor.cursor_y == self.editor_rows.number_of_row() {
+            self.editor_rows
+                .insert_row(self.editor_rows.number_of_row(), String::new());
+        }
+        let row = self.editor_rows.get_editor_row_mut(cursor.cursor_y);
+        if self.insert_mode || cursor.cursor_x >= row.char_len() {
+            row.insert_char(cursor.cursor_x, ch);
+        } else {
+            row.replace_char(cursor.cursor_x, ch);
+        }
+        cursor.cursor_x += 1;
+        self.update_syntax(self.cursor_controller.cursor_y);
+        self.dirty += 1;
+    }
+
+    /// カーソル位置で行を分ける
+    fn break_line(&mut self) {
+        let (x, y) = (
+            self.cursor_controller.cursor_x,
+            self.cursor_controller.cursor_y,
+        );
+        if x == 0 {
+            self.editor_rows.insert_row(y, String::new());
+        } else {
+            let current_row = self.editor_rows.get_editor_row_mut(y);
+            let at = current_row.byte_index(x);
+            let new_row_content = current_row.row_content[at..].into();
+            current_row.row_content.truncate(at);
+            EditorRows::render_row(current_row);
+            self.editor_rows.insert_row(y + 1, new_row_content);
+            self.update_syntax(y);
+            self.update_syntax(y + 1);
+        }
+        self.cursor_controller.cursor_x = 0;
+        self.cursor_controller.cursor_y += 1;
+        self.dirty += 1;
+    }
+
+    /// 現在の行の先頭からインデントを 1 段 (タブ 1 つか `indent_size` 個までの空白) 取り除く
+    pub fn outdent(&mut self, indent_size: usize) {
+        let cursor_y = self.cursor_controller.cursor_y;
+        if cursor_y >= self.editor_rows.number_of_row() {
+            return;
+        }
+        let row = self.editor_rows.get_editor_row_mut(cursor_y);
+        let width = if row.row_content.starts_with('\t') {
+            1
+        } else {
+            row.row_content
+                .chars()
+                .take(indent_size)
+                .take_while(|c| *c == ' ')
+                .count()
+        };
+        if width == 0 {
+            return;
+        }
+        row.row_content.drain(..width);
+        EditorRows::render_row(row);
+        self.update_syntax(cursor_y);
+        self.cursor_controller.cursor_x = self.cursor_controller.cursor_x.saturating_sub(width);
+        self.dirty += 1;
+    }
+
+    /// 現在の行をまるごと削除して内容を返す。最後の 1 行は削除せず空にする。
+    /// カーソルは次の行 (無ければ前の行) の同じ列か行末に移る
+    pub fn delete_line(&mut self) -> Option<String> {
+        let cursor_y = self.cursor_controller.cursor_y;
+        let number_of_rows = self.editor_rows.number_of_row();
+        if cursor_y >= number_of_rows {
+            return None;
+        }
+        let deleted = if number_of_rows == 1 {
+            let row = self.editor_rows.get_editor_row_mut(0);
+            let deleted = std::mem::take(&mut row.row_content);
+            EditorRows::render_row(row);
+            deleted
+        } else {
+            self.editor_rows.delete_row(cursor_y)
+        };
+        let cursor_y = cursor_y.min(self.editor_rows.number_of_row() - 1);
+        // 削除した行の後ろの行は前の行の状態が変わっているので塗り直す
+        self.update_syntax(cursor_y);
+        self.cursor_controller.cursor_y = cursor_y;
+        self.cursor_controller.cursor_x = self
+            .cursor_controller
+            .cursor_x
+            .min(self.editor_rows.get_editor_row(cursor_y).char_len());
+        self.dirty += 1;
+        Some(deleted)
+    }
+
+    /// カーソルの前の文字を削除する。行頭では前の行に連結する
+    pub fn delete_char(&mut self) {
+        let cursor = &mut self.cursor_controller;
+        if cursor.cursor_y == self.editor_rows.number_of_row() {
+            return;
+        }
+        if cursor.cursor_y == 0 && cursor.cursor_x == 0 {
+            return;
+        }
+
+        if cursor.cursor_x > 0 {
+            self.editor_rows
+                .get_editor_row_mut(cursor.cursor_y)
+                .delete_char(cursor.cursor_x - 1);
+            cursor.cursor_x -= 1;
+        } else {
+            cursor.cursor_x = self
+                .editor_rows
+                .get_editor_row(cursor.cursor_y - 1)
+                .char_len();
+            self.editor_rows.join_adjacent_rows(cursor.cursor_y);
+            cursor.cursor_y -= 1;
+        }
+        self.update_syntax(self.cursor_controller.cursor_y);
+        self.dirty += 1;
+    }
+
+    /// 現在の行に次の行を連結し、カーソルを連結位置に置く。
+    /// 次の行の先頭の空白は取り除き、どちらの行も空でなければ空白 1 つで区切る
+    pub fn join_lines(&mut self) {
+        let cursor_y = self.cursor_controller.cursor_y;
+        if cursor_y + 1 >= self.editor_rows.number_of_row() {
+            return;
+        }
+        let current = &self.editor_rows.get_editor_row(cursor_y).row_content;
+        let next = self.editor_rows.get_row(cursor_y + 1).trim_start();
+        let separator =
+            if current.is_empty() || current.ends_with(char::is_whitespace) || next.is_empty() {
+                ""
+            } else {
+                " "
+            };
+        let next = format!("{}{}", separator, next);
+        self.cursor_controller.cursor_x = current.chars().count();
+        self.editor_rows
+            .get_editor_row_mut(cursor_y + 1)
+            .row_content = next;
+        self.editor_rows.join_adjacent_rows(cursor_y + 1);
+        self.update_syntax(cursor_y);
+        self.dirty += 1;
+    }
+
+    /// カーソルのある段落 (空行で区切られた行の並び) を `width` 列に収まるよう単語の境目で折り返し直す。
+    /// 先頭行のインデントと行コメントの記号は各行の先頭に付け直す。段落が無ければ `false` を返す
+    pub fn reflow_paragraph(&mut self, width: usize) -> bool {
+        let comment = self
+            .syntax_highlight
+            .as_ref()
+            .map(|it| it.comment_start().to_string())
+            .filter(|comment| !comment.is_empty());
+        let is_comment = |line: &str| {
+            comment
+                .as_deref()
+                .is_some_and(|it| line.trim_start().starts_with(it))
+        };
+        let rows = &self.editor_rows;
+        let cursor_y = self.cursor_controller.cursor_y;
+        if cursor_y >= rows.number_of_row() || rows.get_row(cursor_y).trim().is_empty() {
+            return false;
+        }
+        // コメントの行とそうでない行は別の段落として扱う
+        let in_paragraph = |y: usize| {
+            let line = rows.get_row(y);
+            !line.trim().is_empty() && is_comment(line) == is_comment(rows.get_row(cursor_y))
+        };
+        let mut start = cursor_y;
+        while start > 0 && in_paragraph(start - 1) {
+            start -= 1;
+        }
+        let mut end = cursor_y;
+        while end + 1 < rows.number_of_row() && in_paragraph(end + 1) {
+            end += 1;
+        }
+
+        // 行頭の空白とコメントの記号を除いた本文と、除いた部分の文字数
+        let strip = |line: &str| -> (String, usize) {
+            let mut body = line.trim_start();
+            if let Some(rest) = comment.as_deref().and_then(|it| body.strip_prefix(it)) {
+                body = rest.trim_start();
+            }
+            (
+                body.to_string(),
+                line.chars().count() - body.chars().count(),
+            )
+        };
+        let first = rows.get_row(start);
+        let prefix: String = {
+            let (_, stripped) = strip(first);
+            let prefix: String = first.chars().take(stripped).collect();
+            if prefix.trim().is_empty() || prefix.ends_with(char::is_whitespace) {
+                prefix
+            } else {
+                prefix + " "
+            }
+        };
+
+        // カーソルより前にある本文の空白以外の文字数を数え、折り返した後も同じ文字の後ろに置く
+        let mut before_cursor = 0;
+        let mut words = Vec::new();
+        for y in start..=end {
+            let (body, stripped) = strip(rows.get_row(y));
+            if y < cursor_y {
+                before_cursor += body.chars().filter(|c| !c.is_whitespace()).count();
+            } else if y == cursor_y {
+                let x = self.cursor_controller.cursor_x.saturating_sub(stripped);
+                before_cursor += body.chars().take(x).filter(|c| !c.is_whitespace()).count();
+            }
+            words.extend(body.split_whitespace().map(String::from));
+        }
+        // 記号だけの行 (中身のないコメントなど) は折り返す本文が無い
+        if words.is_empty() {
+            return false;
+        }
+
+        let prefix_width = prefix.chars().fold(0, |acc, c| {
+            acc + if c == '\t' {
+                row::tab_width() - acc % row::tab_width()
+            } else {
+                1
+            }
+        });
+        let mut lines: Vec<String> = Vec::new();
+        let mut line_width = 0;
+        for word in words {
+            let word_width = word.width();
+            match lines.last_mut() {
+                Some(line) if line_width + 1 + word_width <= width => {
+                    line.push(' ');
+                    line.push_str(&word);
+                    line_width += 1 + word_width;
+                }
+                _ => {
+                    lines.push(format!("{}{}", prefix, word));
+                    line_width = prefix_width + word_width;
+                }
+            }
+        }
+
+        // 折り返しても変わらなければ編集しない
+        if lines
+            .iter()
+            .map(String::as_str)
+            .eq((start..=end).map(|y| self.editor_rows.get_row(y)))
+        {
+            return true;
+        }
+
+        let end_x = self.editor_rows.get_editor_row(end).char_len();
+        self.editor_rows.delete_range((0, start), (end_x, end));
+        let new_end = self.editor_rows.insert_text((0, start), &lines.join("\n"));
+        for at in start..=new_end.1 {
+            self.update_syntax(at);
+        }
+
+        let (mut y, mut x) = (start, prefix.chars().count());
+        let mut remaining = before_cursor;
+        for (i, line) in lines.iter().enumerate() {
+            y = start + i;
+            x = prefix.chars().count();
+            for c in line.chars().skip(x) {
+                if remaining == 0 {
+                    break;
+                }
+                remaining -= usize::from(!c.is_whitespace());
+                x += 1;
+            }
+            if remaining == 0 {
+                break;
+            }
+        }
+        self.cursor_controller.cursor_y = y;
+        self.cursor_controller.cursor_x = x;
+        self.dirty += 1;
+        true
+    }
+
+    fn trim_trailing_whitespace(&mut self) {
+        for at in 0..self.editor_rows.number_of_row() {
+            let row = self.editor_rows.get_editor_row_mut(at);
+            let len = row.row_content.trim_end().len();
+            if len == row.row_content.len() {
+                continue;
+            }
+            row.row_content.truncate(len);
+            EditorRows::render_row(row);
+            self.update_syntax(at);
+        }
+        let cursor = &mut self.cursor_controller;
+        if cursor.cursor_y < self.editor_rows.number_of_row() {
+            cursor.cursor_x = cursor
+                .cursor_x
+                .min(self.editor_rows.get_editor_row(cursor.cursor_y).char_len());
+        }
+    }
+
+    /// ファイルに書き込み、書き込んだバイト数を返す。
+    /// 行末の空白を取り除く設定ならバッファからも取り除く
+    pub fn save(&mut self, options: SaveOptions) -> io::Result<usize> {
+        self.save_with(options, EditorRows::save)
+    }
+
+    /// 権限の無いファイルを `sudo` 経由で書き込む (`save` と同じく変更状態を戻す)
+    pub fn save_with_sudo(&mut self, options: SaveOptions) -> io::Result<usize> {
+        self.save_with(options, EditorRows::save_with_sudo)
+    }
+
+    fn save_with(
+        &mut self,
+        options: SaveOptions,
+        write: fn(&mut EditorRows, SaveOptions) -> io::Result<usize>,
+    ) -> io::Result<usize> {
+        if options.trim_trailing_whitespace {
+            self.trim_trailing_whitespace();
+        }
+        let len = write(&mut self.editor_rows, options)?;
+        self.dirty = 0;
+        self.last_saved = Some(SystemTime::now());
+        Ok(len)
+    }
+}
+
+/// すべて大文字なら小文字に、すべて小文字なら単語の先頭だけ大文字に、それ以外は大文字にする。
+/// `ß` のように大文字にすると長さが変わる文字もある
+fn cycle_case(text: &str) -> String {
+    let (upper, lower) = (text.to_uppercase(), text.to_lowercase());
+    if text == upper && text != lower {
+        lower
+    } else if text == lower && text != upper {
+        let mut word_start = true;
+        text.chars()
+            .flat_map(|c| {
+                let converted: Vec<char> = if word_start {
+                    c.to_uppercase().collect()
+                } else {
+                    c.to_lowercase().collect()
+                };
+                word_start = !c.is_alphanumeric();
+                converted
+            })
+            .collect()
+    } else {
+        upper
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_text(text: &str) -> Document {
+        let mut editor_rows = EditorRows::new(None);
+        editor_rows.set_contents(text);
+        Document::new(editor_rows, (80, 24))
+    }
+
+    fn lines(document: &Document) -> Vec<&str> {
+        let rows = document.editor_rows();
+        (0..rows.number_of_row())
+            .map(|at| rows.get_row(at))
+            .collect()
+    }
+
+    fn move_to(document: &mut Document, x: usize, y: usize) {
+        document.cursor_controller.cursor_x = x;
+        document.cursor_controller.cursor_y = y;
+    }
+
+    const AUTO_INDENT: EditOptions = EditOptions {
+        soft_tabs: true,
+        indent_size: 4,
+        auto_indent: true,
+    };
+
+    #[test]
+    fn typing_past_the_last_row_adds_a_row_as_one_edit() {
+        let mut document = from_text("");
+        document.insert_char('a', EditOptions::default());
+        assert_eq!(lines(&document), ["a"]);
+        assert_eq!(document.edits_since_save(), 1);
+    }
+
+    #[test]
+    fn overwrite_mode_replaces_until_the_end_of_the_line() {
+        let mut document = from_text("ab");
+        document.insert_mode = false;
+        "xyz"
+            .chars()
+            .for_each(|ch| document.insert_char(ch, EditOptions::default()));
+        assert_eq!(lines(&document), ["xyz"]);
+    }
+
+    #[test]
+    fn typing_replaces_the_selection() {
+        let mut document = from_text("hello world");
+        document.selection_anchor = Some((0, 0));
+        move_to(&mut document, 5, 0);
+        document.insert_char('J', EditOptions::default());
+        assert_eq!(lines(&document), ["J world"]);
+        assert_eq!(document.selection(), None);
+    }
+
+    #[test]
+    fn soft_tab_fills_to_the_next_indent_stop() {
+        let mut document = from_text("ab");
+        move_to(&mut document, 2, 0);
+        document.insert_tab(AUTO_INDENT);
+        assert_eq!(lines(&document), ["ab  "]);
+    }
+
+    #[test]
+    fn newline_keeps_the_indent_and_indents_after_an_open_bracket() {
+        let mut document = from_text("    if x {");
+        move_to(&mut document, 10, 0);
+        document.insert_newline(AUTO_INDENT);
+        assert_eq!(lines(&document), ["    if x {", "        "]);
+        assert_eq!(document.cursor(), (8, 1));
+
+        let mut document = from_text("    foo");
+        move_to(&mut document, 7, 0);
+        document.insert_newline(EditOptions::default());
+        assert_eq!(lines(&document), ["    foo", ""]);
+    }
+
+    #[test]
+    fn backspace_in_soft_indent_deletes_to_the_previous_tab_stop() {
+        let mut document = from_text("      x");
+        move_to(&mut document, 6, 0);
+        document.backspace(AUTO_INDENT);
+        assert_eq!(lines(&document), ["    x"]);
+        assert_eq!(document.cursor(), (4, 0));
+        document.backspace(EditOptions::default());
+        assert_eq!(lines(&document), ["   x"]);
+    }
+
+    #[test]
+    fn backspace_at_the_start_of_a_line_joins_it_to_the_previous_line() {
+        let mut document = from_text("ab\ncd");
+        move_to(&mut document, 0, 1);
+        document.backspace(EditOptions::default());
+        assert_eq!(lines(&document), ["abcd"]);
+        assert_eq!(document.cursor(), (2, 0));
+    }
+
+    #[test]
+    fn delete_to_end_of_line_returns_the_text_and_joins_at_the_end() {
+        let mut document = from_text("héllo\nworld");
+        move_to(&mut document, 2, 0);
+        assert_eq!(document.delete_to_end_of_line().as_deref(), Some("llo"));
+        assert_eq!(document.delete_to_end_of_line().as_deref(), Some("\n"));
+        assert_eq!(lines(&document), ["héworld"]);
+        move_to(&mut document, 7, 0);
+        assert_eq!(document.delete_to_end_of_line(), None);
+    }
+
+    #[test]
+    fn delete_to_start_of_line_can_keep_the_indent() {
+        let mut document = from_text("    foo bar");
+        move_to(&mut document, 8, 0);
+        assert_eq!(
+            document.delete_to_start_of_line(true).as_deref(),
+            Some("foo ")
+        );
+        assert_eq!(lines(&document), ["    bar"]);
+        assert_eq!(
+            document.delete_to_start_of_line(false).as_deref(),
+            Some("    ")
+        );
+        assert_eq!(lines(&document), ["bar"]);
+        assert_eq!(document.delete_to_start_of_line(false), None);
+    }
+
+    #[test]
+    fn insert_str_places_the_cursor_after_multiline_text() {
+        let mut document = from_text("ac");
+        move_to(&mut document, 1, 0);
+        let (start, end) = document.insert_str("b1\nb2");
+        assert_eq!(lines(&document), ["ab1", "b2c"]);
+        assert_eq!((start, end), ((1, 0), (2, 1)));
+        assert_eq!(document.cursor(), end);
+        assert_eq!(document.edits_since_save(), 1);
+    }
+
+    #[test]
+    fn insert_lines_goes_below_or_above_the_current_line() {
+        let mut document = from_text("one\ntwo");
+        document.insert_lines("x\ny\n", false);
+        assert_eq!(lines(&document), ["one", "x", "y", "two"]);
+        assert_eq!(document.cursor(), (0, 1));
+        document.insert_lines("z\n", true);
+        assert_eq!(lines(&document), ["one", "z", "x", "y", "two"]);
+    }
+
+    #[test]
+    fn toggle_case_cycles_the_word_under_the_cursor() {
+        let mut document = from_text("say hello");
+        move_to(&mut document, 6, 0);
+        document.toggle_case();
+        assert_eq!(lines(&document), ["say Hello"]);
+        document.toggle_case();
+        assert_eq!(lines(&document), ["say HELLO"]);
+        document.toggle_case();
+        assert_eq!(lines(&document), ["say hello"]);
+        assert_eq!(document.cursor(), (6, 0));
+        assert_eq!(document.selection(), None);
+    }
+
+    #[test]
+    fn toggle_case_reselects_the_converted_selection() {
+        let mut document = from_text("straße");
+        document.selection_anchor = Some((0, 0));
+        move_to(&mut document, 6, 0);
+        document.toggle_case();
+        document.toggle_case();
+        assert_eq!(lines(&document), ["STRASSE"]);
+        assert_eq!(document.selection(), Some(((0, 0), (7, 0))));
+    }
+
+    #[test]
+    fn sort_lines_sorts_the_given_rows() {
+        let mut document = from_text("c\nB\na\nz");
+        document.sort_lines(0, 2, false, false);
+        assert_eq!(lines(&document), ["B", "a", "c", "z"]);
+        document.sort_lines(0, 2, true, true);
+        assert_eq!(lines(&document), ["c", "B", "a", "z"]);
+        assert_eq!(document.cursor(), (0, 0));
+    }
+
+    #[test]
+    fn block_selection_edits_every_row_in_the_column_range() {
+        let mut document = from_text("abcd\nab\nabcd");
+        document.toggle_block_selection();
+        move_to(&mut document, 0, 0);
+        document.selection_anchor = Some((1, 0));
+        move_to(&mut document, 3, 2);
+        assert_eq!(document.block_text().as_deref(), Some("bc\nb\nbc"));
+        document.insert_char('X', EditOptions::default());
+        assert_eq!(lines(&document), ["aXd", "aX", "aXd"]);
+        assert_eq!(document.edits_since_save(), 1);
+        document.backspace(EditOptions::default());
+        assert_eq!(lines(&document), ["ad", "a", "ad"]);
+    }
+
+    #[test]
+    fn delete_selection_clears_the_selection() {
+        let mut document = from_text("one\ntwo\nthree");
+        document.selection_anchor = Some((1, 0));
+        move_to(&mut document, 2, 2);
+        assert!(document.delete_selection());
+        assert_eq!(lines(&document), ["oree"]);
+        assert_eq!(document.cursor(), (1, 0));
+        assert!(!document.delete_selection());
+    }
+
+    #[test]
+    fn cycle_case_goes_upper_lower_title() {
+        assert_eq!(cycle_case("MiXed"), "MIXED");
+        assert_eq!(cycle_case("MIXED"), "mixed");
+        assert_eq!(cycle_case("two words"), "Two Words");
+        assert_eq!(cycle_case("123"), "123");
+    }
+
+    #[test]
+    fn set_line_ending_counts_only_real_changes() {
+        let mut document = from_text("a\nb");
+        assert!(!document.set_line_ending(LineEnding::Lf));
+        document.editor_rows_mut().mixed_line_endings = true;
+        assert!(document.set_line_ending(LineEnding::Lf));
+        assert!(!document.editor_rows().mixed_line_endings);
+        assert_eq!(document.edits_since_save(), 1);
+
+        assert!(!document.set_line_ending(LineEnding::Lf));
+        assert!(document.set_line_ending(LineEnding::Crlf));
+        assert!(document.editor_rows().line_ending == LineEnding::Crlf);
+        assert_eq!(document.edits_since_save(), 2);
+    }
+
+    #[test]
+    fn soft_tabs_align_after_a_literal_tab() {
+        let mut document = from_text("\t  foo");
+        let row = document.editor_rows().get_editor_row(0);
+        // タブの途中の列はタブの位置、その後は空白と文字の位置になる
+        assert_eq!(row.get_row_content_x(5), 0);
+        assert_eq!(row.get_row_content_x(9), 2);
+        assert_eq!(row.get_row_content_x(10), 3);
+        assert_eq!(row.render_x(3), 10);
+
+        move_to(&mut document, 3, 0);
+        document.insert_tab(AUTO_INDENT);
+        assert_eq!(lines(&document), ["\t    foo"]);
+        assert_eq!(document.cursor(), (5, 0));
+
+        // 前のインデント位置 (タブの直後) まで空白を消す
+        document.backspace(AUTO_INDENT);
+        assert_eq!(lines(&document), ["\tfoo"]);
+        assert_eq!(document.cursor(), (1, 0));
+    }
+
+    #[test]
+    fn toggle_case_maps_non_ascii_letters() {
+        assert_eq!(cycle_case("émile"), "Émile");
+        assert_eq!(cycle_case("Émile"), "ÉMILE");
+        assert_eq!(cycle_case("ÉMILE"), "émile");
+
+        let mut document = from_text("ça straße va");
+        move_to(&mut document, 5, 0);
+        document.toggle_case();
+        assert_eq!(lines(&document), ["ça Straße va"]);
+        document.toggle_case();
+        // `ß` は `SS` になり行が 1 文字伸びる
+        assert_eq!(lines(&document), ["ça STRASSE va"]);
+        assert_eq!(document.cursor(), (5, 0));
+        document.toggle_case();
+        assert_eq!(lines(&document), ["ça strasse va"]);
+    }
+
+    #[test]
+    fn delete_line_removes_the_first_middle_and_last_lines() {
+        let mut document = from_text("one\ntwo\nthree");
+        move_to(&mut document, 2, 0);
+        assert_eq!(document.delete_line().as_deref(), Some("one"));
+        assert_eq!(lines(&document), ["two", "three"]);
+        assert_eq!(document.cursor(), (2, 0));
+
+        let mut document = from_text("one\ntwo\nthree");
+        move_to(&mut document, 3, 1);
+        assert_eq!(document.delete_line().as_deref(), Some("two"));
+        assert_eq!(lines(&document), ["one", "three"]);
+        assert_eq!(document.cursor(), (3, 1));
+
+        // 最後の行を消すとカーソルは前の行に移り、行末に収まる
+        let mut document = from_text("one\ntwo\nthree");
+        move_to(&mut document, 5, 2);
+        assert_eq!(document.delete_line().as_deref(), Some("three"));
+        assert_eq!(lines(&document), ["one", "two"]);
+        assert_eq!(document.cursor(), (3, 1));
+        assert_eq!(document.edits_since_save(), 1);
+    }
+
+    #[test]
+    fn delete_line_empties_the_only_line() {
+        let mut document = from_text("only");
+        move_to(&mut document, 4, 0);
+        assert_eq!(document.delete_line().as_deref(), Some("only"));
+        assert_eq!(lines(&document), [""]);
+        assert_eq!(document.cursor(), (0, 0));
+
+        move_to(&mut document, 0, 1);
+        assert_eq!(document.delete_line(), None);
+    }
+
+    fn type_text(document: &mut Document, text: &str, options: EditOptions) {
+        text.chars().for_each(|ch| match ch {
+            '\n' => document.insert_newline(options),
+            ch => document.insert_char(ch, options),
+        });
+    }
+
+    #[test]
+    fn closing_bracket_at_the_start_of_a_line_dedents() {
+        let mut document = from_text("");
+        type_text(&mut document, "fn f() {\nif x {\ny();\n}\n}", AUTO_INDENT);
+        assert_eq!(
+            lines(&document),
+            ["fn f() {", "    if x {", "        y();", "    }", "}"]
+        );
+        assert_eq!(document.cursor(), (1, 4));
+
+        // 行の途中の閉じ括弧ではインデントを変えない
+        let mut document = from_text("    a");
+        move_to(&mut document, 5, 0);
+        type_text(&mut document, "}", AUTO_INDENT);
+        assert_eq!(lines(&document), ["    a}"]);
+    }
+
+    #[test]
+    fn closing_bracket_keeps_the_indent_without_auto_indent() {
+        let mut document = from_text("    ");
+        move_to(&mut document, 4, 0);
+        document.insert_char('}', EditOptions::default());
+        assert_eq!(lines(&document), ["    }"]);
+    }
+
+    #[test]
+    fn reflow_paragraph_rewraps_the_words() {
+        let mut document = from_text("one two\nthree four five\n\nnext");
+        assert!(document.reflow_paragraph(10));
+        assert_eq!(
+            lines(&document),
+            ["one two", "three four", "five", "", "next"]
+        );
+        assert_eq!(document.edits_since_save(), 1);
+    }
+
+    #[test]
+    fn reflow_paragraph_leaves_bare_comment_lines_alone() {
+        let mut document = from_text("//\n//\n//");
+        document.syntax_highlight = Some(Rc::new(crate::editor::output::RustHighlight::new()));
+        move_to(&mut document, 0, 1);
+        assert!(!document.reflow_paragraph(80));
+        assert_eq!(lines(&document), ["//", "//", "//"]);
+        assert!(!document.is_dirty());
+    }
+
+    #[test]
+    fn reflow_paragraph_does_not_edit_an_already_wrapped_paragraph() {
+        let mut document = from_text("one two\nthree");
+        move_to(&mut document, 2, 1);
+        assert!(document.reflow_paragraph(10));
+        assert_eq!(lines(&document), ["one two", "three"]);
+        assert_eq!(document.edits_since_save(), 0);
+        assert_eq!(document.cursor(), (2, 1));
+    }
+}
@@ -0,0 +1,38 @@
+use std::{fs, io, path::Path};
+
+/// カレントディレクトリ直下のファイル名を名前順に返す (サブディレクトリは辿らない)
+pub fn list_files() -> io::Result<Vec<String>> {
+    let mut files: Vec<String> = fs::read_dir(".")?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|it| it.is_file()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// `dir` 直下の項目を名前順に返す。ディレクトリは末尾に `/` を付けて先に並べ、
+/// 先頭に親ディレクトリ `../` を置く
+pub fn list_entries(dir: &Path) -> io::Result<Vec<String>> {
+    let mut entries: Vec<(bool, String)> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let is_dir = entry.file_type().is_ok_and(|it| it.is_dir());
+            let name = entry.file_name().into_string().ok()?;
+            Some((!is_dir, if is_dir { name + "/" } else { name }))
+        })
+        .collect();
+    entries.sort();
+    Ok(std::iter::once("../".to_string())
+        .chain(entries.into_iter().map(|(_, name)| name))
+        .collect())
+}
+
+/// `pattern` の文字が `name` に順番通りに現れれば一致とみなす (大文字小文字は区別しない)
+pub fn fuzzy_match(pattern: &str, name: &str) -> bool {
+    let mut name = name.chars().flat_map(char::to_lowercase);
+    pattern
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|p| name.any(|c| c == p))
+}
@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use crossterm::{
     queue,
     style::{Color, SetForegroundColor},
@@ -5,6 +7,9 @@ use crossterm::{
 
 use super::{row::Row, EditorContents};
 
+/// 括弧の入れ子の深さごとに順に使う色
+const BRACKET_COLORS: [Color; 3] = [Color::DarkYellow, Color::Magenta, Color::Blue];
+
 #[derive(Copy, Clone)]
 pub enum HighlightType {
     Normal,
@@ -12,20 +17,360 @@ pub enum HighlightType {
     SearchMatch,
     String,
     CharLiteral,
+    Escape,
     Comment,
+    Function,
+    Macro,
     Other(Color),
 }
 
+/// 行の中で対応の取れなかった括弧の数。行頭の深さが `depth` なら行末の深さは
+/// `depth.saturating_sub(close) + open` になる
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Brackets {
+    /// 前の行までに開いた括弧を閉じた数
+    pub close: usize,
+    /// 行末で閉じていない括弧の数
+    pub open: usize,
+}
+
+impl Brackets {
+    pub fn apply(self, depth: usize) -> usize {
+        depth.saturating_sub(self.close) + self.open
+    }
+}
+
+/// 行をまたいで引き継ぐハイライトの状態
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineState {
+    /// 行を塗ったときの行頭の括弧の深さ
+    pub start_depth: usize,
+    pub brackets: Brackets,
+    /// 行末で複数行コメントの途中
+    pub in_comment: bool,
+}
+
+impl LineState {
+    /// 行末の括弧の深さ
+    pub fn end_depth(&self) -> usize {
+        self.brackets.apply(self.start_depth)
+    }
+}
+
+impl HighlightType {
+    /// 既定の色 (シンタックスが無い行に重ねるハイライトにも使う)
+    pub fn color(&self) -> Color {
+        match self {
+            HighlightType::Normal => Color::Reset,
+            HighlightType::Number => Color::Cyan,
+            HighlightType::SearchMatch => Color::Blue,
+            HighlightType::String => Color::Green,
+            HighlightType::CharLiteral => Color::DarkGreen,
+            HighlightType::Escape => Color::Magenta,
+            HighlightType::Comment => Color::DarkGrey,
+            HighlightType::Function => Color::Yellow,
+            HighlightType::Macro => Color::DarkCyan,
+            HighlightType::Other(color) => *color,
+        }
+    }
+}
+
 pub trait SyntaxHighlight {
-    fn syntax_color(&self, highlight_type: &HighlightType) -> Color;
-    fn update_syntax(&self, at: usize, editor_rows: &mut Vec<Row>);
-    fn extensions(&self) -> &[&str];
+    fn extensions(&self) -> &[String];
     fn file_type(&self) -> &str;
     fn comment_start(&self) -> &str;
+    fn keywords(&self) -> &[(Color, Vec<String>)];
+
+    /// 複数行コメントの開始と終了
+    fn multiline_comment(&self) -> Option<(&str, &str)> {
+        None
+    }
+
+    fn syntax_color(&self, highlight_type: &HighlightType) -> Color {
+        highlight_type.color()
+    }
+
+    /// 行をハイライトする。行末で複数行コメントの途中かどうかが変わった間だけ後続の行も塗り直し、
+    /// 変わらなくなったところで止める。括弧の深さが変わっても後続の行は塗り直さず、
+    /// 表示するときに `refresh_brackets` で直す
+    fn update_syntax(&self, at: usize, editor_rows: &mut [Row]) {
+        let mut at = at;
+        while self.highlight_row(at, editor_rows) && at + 1 < editor_rows.len() {
+            at += 1;
+        }
+    }
+
+    /// 1 行分のハイライトを作り、行末で複数行コメントの途中かどうかが前回から変わったかを返す
+    fn highlight_row(&self, at: usize, editor_rows: &mut [Row]) -> bool {
+        let depth = at
+            .checked_sub(1)
+            .map_or(0, |previous| editor_rows[previous].end_state.end_depth());
+        self.highlight_row_from(at, depth, editor_rows)
+    }
+
+    /// `rows` の行 (画面に表示する行) のうち、塗ったときから行頭の括弧の深さが変わった行を塗り直す。
+    /// 先頭の `checked` 行は塗ったときの深さが正しいものとして、`rows` の前の行の行末の深さから始める。
+    /// 正しいとわかっている行の数を返す
+    fn refresh_brackets(
+        &self,
+        rows: Range<usize>,
+        checked: usize,
+        editor_rows: &mut [Row],
+    ) -> usize {
+        let start = checked.min(rows.start);
+        // 正しい深さがわかっている行から、表示する行の前までは括弧の出入りだけを数える
+        let mut depth = editor_rows[start..rows.start].iter().fold(
+            start
+                .checked_sub(1)
+                .map_or(0, |previous| editor_rows[previous].end_state.end_depth()),
+            |depth, row| row.end_state.brackets.apply(depth),
+        );
+        for at in rows.clone() {
+            if editor_rows[at].end_state.start_depth != depth {
+                self.highlight_row_from(at, depth, editor_rows);
+            }
+            depth = editor_rows[at].end_state.end_depth();
+        }
+        if checked < rows.start {
+            checked
+        } else {
+            checked.max(rows.end)
+        }
+    }
+
+    /// 行頭の括弧の深さを `depth` として 1 行分のハイライトを作る
+    fn highlight_row_from(&self, at: usize, depth: usize, editor_rows: &mut [Row]) -> bool {
+        let start_depth = depth;
+        let mut depth = depth;
+        let mut brackets = Brackets::default();
+        let mut in_comment = at
+            .checked_sub(1)
+            .is_some_and(|previous| editor_rows[previous].end_state.in_comment);
+        let current_row = &mut editor_rows[at];
+        macro_rules! add {
+            ($h:expr) => {
+                current_row.highlight.push($h)
+            };
+        }
+
+        let controls = current_row.controls().to_vec();
+        current_row.highlight = Vec::with_capacity(current_row.render.len());
+        let render = current_row.render.as_bytes();
+        let mut i = 0;
+        let mut previous_separator = true;
+        let mut in_string: Option<char> = None;
+        let comment_start = self.comment_start().as_bytes();
+        let (multiline_start, multiline_end) = self
+            .multiline_comment()
+            .map_or((&[][..], &[][..]), |(start, end)| {
+                (start.as_bytes(), end.as_bytes())
+            });
+
+        while i < render.len() {
+            let c = render[i] as char;
+            if in_comment {
+                let len = if render[i..].starts_with(multiline_end) {
+                    in_comment = false;
+                    multiline_end.len()
+                } else {
+                    1
+                };
+                (i..i + len).for_each(|_| add!(HighlightType::Comment));
+                i += len;
+                previous_separator = true;
+                continue;
+            }
+            if in_string.is_none() && !comment_start.is_empty() {
+                let end = i + comment_start.len();
+                if render[i..end.min(render.len())] == *comment_start {
+                    (i..render.len()).for_each(|_| add!(HighlightType::Comment));
+                    break;
+                }
+            }
+            if in_string.is_none()
+                && !multiline_start.is_empty()
+                && render[i..].starts_with(multiline_start)
+            {
+                (i..i + multiline_start.len()).for_each(|_| add!(HighlightType::Comment));
+                i += multiline_start.len();
+                in_comment = true;
+                continue;
+            }
+
+            // 制御文字の表記 (`^[` や `<9b>`) は括弧や数値として扱わない
+            if let Some(control) = controls.iter().find(|range| range.start == i) {
+                let highlight = match in_string {
+                    Some('"') => HighlightType::String,
+                    Some(_) => HighlightType::CharLiteral,
+                    None => HighlightType::Normal,
+                };
+                (i..control.end).for_each(|_| add!(highlight));
+                i = control.end;
+                previous_separator = true;
+                continue;
+            }
+
+            if let Some(val) = in_string {
+                if c == '\\' && i + 1 < render.len() {
+                    let mut end = i + 2;
+                    match render[i + 1] {
+                        b'u' if render.get(end) == Some(&b'{') => {
+                            end = render[end..]
+                                .iter()
+                                .position(|b| *b == b'}')
+                                .map_or(end, |p| end + p + 1);
+                        }
+                        b'x' => {
+                            while end < (i + 4).min(render.len()) && render[end].is_ascii_hexdigit()
+                            {
+                                end += 1;
+                            }
+                        }
+                        _ => {}
+                    }
+                    (i..end).for_each(|_| add!(HighlightType::Escape));
+                    i = end;
+                    continue;
+                }
+
+                add! {
+                    if val == '"' {HighlightType::String} else {HighlightType::CharLiteral}
+                }
+
+                if val == c {
+                    in_string = None;
+                }
+                i += 1;
+                previous_separator = true;
+                continue;
+            } else if c == '"' || c == '\'' {
+                in_string = Some(c);
+                add! {
+                    if c == '"' {HighlightType::String} else {HighlightType::CharLiteral}
+                }
+                i += 1;
+                continue;
+            }
+
+            if c.is_ascii_digit() && previous_separator {
+                let skip = |mut end: usize, accept: fn(&u8) -> bool| {
+                    while render.get(end).is_some_and(accept) {
+                        end += 1;
+                    }
+                    end
+                };
+                let is_digit = |b: &u8| b.is_ascii_digit() || *b == b'_';
+                let mut end = i + 1;
+
+                if c == '0' && matches!(render.get(end), Some(b'x' | b'o' | b'b')) {
+                    // 0x / 0o / 0b 接頭辞付きの整数
+                    end = skip(end + 1, |b| b.is_ascii_hexdigit() || *b == b'_');
+                } else {
+                    end = skip(end, is_digit);
+                    if render.get(end) == Some(&b'.')
+                        && render.get(end + 1).is_some_and(u8::is_ascii_digit)
+                    {
+                        end = skip(end + 1, is_digit);
+                    }
+                    if matches!(render.get(end), Some(b'e' | b'E')) {
+                        let mut exponent = end + 1;
+                        if matches!(render.get(exponent), Some(b'+' | b'-')) {
+                            exponent += 1;
+                        }
+                        if render.get(exponent).is_some_and(u8::is_ascii_digit) {
+                            end = skip(exponent, is_digit);
+                        }
+                    }
+                }
+                // u8 や f64 などの型接尾辞
+                end = skip(end, |b| b.is_ascii_alphanumeric() || *b == b'_');
+
+                (i..end).for_each(|_| add!(HighlightType::Number));
+                i = end;
+                previous_separator = false;
+                continue;
+            }
+
+            if previous_separator {
+                let keyword = self.keywords().iter().find_map(|(color, words)| {
+                    words
+                        .iter()
+                        .find(|word| {
+                            let end = i + word.len();
+                            let is_end_or_sep = render
+                                .get(end)
+                                .map(|c| self.is_separator(*c as char))
+                                .unwrap_or(end == render.len());
+                            is_end_or_sep && render[i..end] == *word.as_bytes()
+                        })
+                        .map(|word| (*color, word.len()))
+                });
+                if let Some((color, len)) = keyword {
+                    (i..i + len).for_each(|_| add!(HighlightType::Other(color)));
+                    i += len;
+                    previous_separator = false;
+                    continue;
+                }
+            }
+
+            let is_identifier = |b: &u8| b.is_ascii_alphanumeric() || *b == b'_';
+            if (c.is_ascii_alphabetic() || c == '_') && (i == 0 || !is_identifier(&render[i - 1])) {
+                let end = render[i..]
+                    .iter()
+                    .position(|b| !is_identifier(b))
+                    .map_or(render.len(), |len| i + len);
+                // 直後に `!` が続く識別子はマクロ呼び出し (`!` も含める)、
+                // `(` が続く識別子は関数呼び出しとみなす
+                let (highlight, end) = match (render.get(end), render.get(end + 1)) {
+                    (Some(b'!'), next) if next != Some(&b'=') => (HighlightType::Macro, end + 1),
+                    (Some(b'('), _) => (HighlightType::Function, end),
+                    _ => (HighlightType::Normal, end),
+                };
+                (i..end).for_each(|_| add!(highlight));
+                i = end;
+                previous_separator = false;
+                continue;
+            }
+
+            // 括弧は入れ子の深さで色を変える (文字列とコメントの中は上で処理済み)
+            match c {
+                '(' | '[' | '{' => {
+                    add!(HighlightType::Other(
+                        BRACKET_COLORS[depth % BRACKET_COLORS.len()]
+                    ));
+                    depth += 1;
+                    brackets.open += 1;
+                }
+                ')' | ']' | '}' => {
+                    depth = depth.saturating_sub(1);
+                    if brackets.open > 0 {
+                        brackets.open -= 1;
+                    } else {
+                        brackets.close += 1;
+                    }
+                    add!(HighlightType::Other(
+                        BRACKET_COLORS[depth % BRACKET_COLORS.len()]
+                    ));
+                }
+                _ => add!(HighlightType::Normal),
+            }
+            previous_separator = self.is_separator(c);
+            i += 1;
+        }
+
+        assert_eq!(current_row.render.len(), current_row.highlight.len());
+        let changed = current_row.end_state.in_comment != in_comment;
+        current_row.end_state = LineState {
+            start_depth,
+            brackets,
+            in_comment,
+        };
+        changed
+    }
 
     fn color_row(&self, render: &str, highlight: &[HighlightType], out: &mut EditorContents) {
         let mut current_color = self.syntax_color(&HighlightType::Normal);
-        render.chars().enumerate().for_each(|(i, c)| {
+        render.char_indices().for_each(|(i, c)| {
             let color = self.syntax_color(&highlight[i]);
             if color != current_color {
                 current_color = color;
@@ -37,15 +382,20 @@ pub trait SyntaxHighlight {
     }
 
     fn is_separator(&self, c: char) -> bool {
-        c.is_whitespace()
-            || [
-                ',', '.', '(', ')', '+', '-', '/', '*', '=', '~', '%', '<', '>', '"', '\'', ';',
-                '&',
-            ]
-            .contains(&c)
+        is_separator(c)
     }
 }
 
+/// 単語の区切りになる文字 (空白と一部の記号)
+pub fn is_separator(c: char) -> bool {
+    c.is_whitespace()
+        || [
+            ',', '.', '(', ')', '[', ']', '{', '}', '+', '-', '/', '*', '=', '~', '%', '<', '>',
+            '"', '\'', ';', '&',
+        ]
+        .contains(&c)
+}
+
 #[macro_export]
 macro_rules! syntax_struct {
     (
@@ -53,37 +403,38 @@ macro_rules! syntax_struct {
                 extensions:$ext:expr,
                 file_type:$type:expr,
                 comment_start:$start:expr,
+                multiline_comment:$multiline:expr,
                 keywords: {
                     $([$color:expr; $($words:expr),*]),*
                 }
             }
 		) => {
-        use crate::editor::output::highlight::HighlightType;
-        use crate::editor::output::row::Row;
+        use $crate::editor::output::highlight::HighlightType;
+        use $crate::editor::output::row::Row;
 
         struct $Name {
-            extensions: &'static [&'static str],
+            extensions: Vec<String>,
             file_type: &'static str,
             comment_start: &'static str,
+            multiline_comment: Option<(&'static str, &'static str)>,
+            keywords: Vec<(Color, Vec<String>)>,
         }
 
         impl $Name {
             fn new() -> Self {
-                $ (
-                    let color = $color;
-                    let keywords = vec!($($words),*);
-                )*
                 Self {
-                    extensions: &$ext,
+                    extensions: $ext.iter().map(|ext| ext.to_string()).collect(),
                     file_type: $type,
                     comment_start: $start,
+                    multiline_comment: $multiline,
+                    keywords: vec![$(($color, vec![$($words.to_string()),*])),*],
                 }
             }
         }
 
         impl SyntaxHighlight for $Name {
-            fn extensions(&self) -> &[&str] {
-                self.extensions
+            fn extensions(&self) -> &[String] {
+                &self.extensions
             }
 
             fn file_type(&self) -> &str {
@@ -94,116 +445,181 @@ macro_rules! syntax_struct {
                 self.comment_start
             }
 
-            fn syntax_color(&self, highlight_type: &HighlightType) -> Color {
-                match highlight_type {
-                    HighlightType::Normal => Color::Reset,
-                    HighlightType::Number => Color::Cyan,
-                    HighlightType::SearchMatch => Color::Blue,
-                    HighlightType::String => Color::Green,
-                    HighlightType::CharLiteral => Color::DarkGreen,
-                    HighlightType::Comment => Color::DarkGrey,
-                    HighlightType::Other(color) => *color,
-                }
+            fn multiline_comment(&self) -> Option<(&str, &str)> {
+                self.multiline_comment
             }
 
-            fn update_syntax(&self, at: usize, editor_rows: &mut Vec<Row>) {
-                let current_row = &mut editor_rows[at];
-                macro_rules! add {
-                    ($h:expr) => {
-                        current_row.highlight.push($h)
-                    };
-                }
+            fn keywords(&self) -> &[(Color, Vec<String>)] {
+                &self.keywords
+            }
+        }
+    };
+}
 
-                current_row.highlight = Vec::with_capacity(current_row.render.len());
-                let render = current_row.render.as_bytes();
-                let mut i = 0;
-                let mut previous_separator = true;
-                let mut in_string: Option<char> = None;
-                let comment_start = self.comment_start().as_bytes();
-
-                while i < render.len() {
-                    let c = render[i] as char;
-                    let previous_highlight = if i > 0 {
-                        current_row.highlight[i - 1]
-                    } else {
-                        HighlightType::Normal
-                    };
-
-                    if in_string.is_none() && !comment_start.is_empty() {
-                        let end = i + comment_start.len();
-                        if render[i..end.min(render.len())] == *comment_start {
-                            (i..render.len()).for_each(|_| add!(HighlightType::Comment));
-                            break;
-                        }
-                    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::output::row::EditorRows;
+    use crate::editor::output::RustHighlight;
 
-                    if let Some(val) = in_string {
-                        add! {
-                            if val == '"' {HighlightType::String} else {HighlightType::CharLiteral}
-                        }
+    fn rows(text: &str) -> Vec<Row> {
+        let mut editor_rows = EditorRows::new(None);
+        editor_rows.set_contents(text);
+        let mut rows = editor_rows.row_contents;
+        let syntax = RustHighlight::new();
+        (0..rows.len()).for_each(|at| syntax.update_syntax(at, &mut rows));
+        rows
+    }
 
-                        if c == '\\' && i + 1 < render.len() {
-                            add! {
-                                if val == '"' {HighlightType::String} else {HighlightType::CharLiteral}
-                            }
-                            i += 2;
-                            continue;
-                        }
+    /// `row` の中の `word` がすべて `kind` で塗られているか
+    fn colored(row: &Row, word: &str, kind: fn(&HighlightType) -> bool) -> bool {
+        let start = row.render.find(word).unwrap();
+        row.highlight[start..start + word.len()].iter().all(kind)
+    }
 
-                        if val == c {
-                            in_string = None;
-                        }
-                        i += 1;
-                        previous_separator = true;
-                        continue;
-                    } else if c == '"' || c == '\'' {
-                        in_string = Some(c);
-                        add! {
-                            if c == '"' {HighlightType::String} else {HighlightType::CharLiteral}
-                        }
-                        i += 1;
-                        continue;
-                    }
+    fn bracket_color(row: &Row, at: usize) -> Option<Color> {
+        match row.highlight[at] {
+            HighlightType::Other(color) => Some(color),
+            _ => None,
+        }
+    }
 
-                    let is_number = c.is_digit(10)
-                        && (previous_separator
-                            || matches!(previous_highlight, HighlightType::Number));
-                    let is_decimal_point =
-                        c == '.' && matches!(previous_highlight, HighlightType::Number);
-
-                    if is_number || is_decimal_point {
-                        add!(HighlightType::Number);
-                        i += 1;
-                        previous_separator = false;
-                        continue;
-                    }
+    #[test]
+    fn brackets_are_colored_by_depth_across_rows() {
+        let rows = rows("fn f() {\n    g(h[0]);\n}");
+        assert_eq!(bracket_color(&rows[0], 7), Some(BRACKET_COLORS[0]));
+        assert_eq!(bracket_color(&rows[1], 5), Some(BRACKET_COLORS[1]));
+        assert_eq!(bracket_color(&rows[1], 7), Some(BRACKET_COLORS[2]));
+        assert_eq!(bracket_color(&rows[1], 9), Some(BRACKET_COLORS[2]));
+        assert_eq!(bracket_color(&rows[2], 0), Some(BRACKET_COLORS[0]));
+        assert_eq!(rows[2].end_state.end_depth(), 0);
+    }
 
-                    if previous_separator {
-                        $ (
-                            $ (
-                                let end = i + $words.len();
-                                let is_end_or_sep = render
-                                    .get(end)
-                                    .map(|c| self.is_separator(*c as char))
-                                    .unwrap_or(end == render.len());
-                                if is_end_or_sep && render[i..end] == *$words.as_bytes() {
-                                    (i..i + $words.len()).for_each(|_| add!(HighlightType::Other($color)));
-                                    i += $words.len();
-                                    previous_separator = false;
-                                    continue;
-                                }
-
-                            )*
-                        )*
-                    }
+    #[test]
+    fn brackets_summarize_unmatched_closes_and_opens() {
+        let rows = rows(") ( ] [ (");
+        let brackets = rows[0].end_state.brackets;
+        assert_eq!((brackets.close, brackets.open), (1, 2));
+        assert_eq!(brackets.apply(0), 2);
+        assert_eq!(brackets.apply(3), 4);
+    }
 
-                    add!(HighlightType::Normal);
-                    previous_separator = self.is_separator(c);
-                    i += 1;
-                }
+    #[test]
+    fn editing_a_bracket_rehighlights_only_the_edited_row() {
+        let syntax = RustHighlight::new();
+        let mut rows = rows(&"a(b)\n".repeat(1000));
+        rows[0].row_content.insert(0, '{');
+        EditorRows::render_row(&mut rows[0]);
+        rows[1..].iter_mut().for_each(|row| row.highlight.clear());
 
-                assert_eq!(current_row.render.len(), current_row.highlight.len())
-            }
+        syntax.update_syntax(0, &mut rows);
+        assert!(rows[1..].iter().all(|row| row.highlight.is_empty()));
+
+        // 表示する行だけを新しい深さで塗り直す
+        assert_eq!(syntax.refresh_brackets(500..510, 1, &mut rows), 1);
+        assert_eq!(bracket_color(&rows[500], 1), Some(BRACKET_COLORS[1]));
+        assert_eq!(rows[509].end_state.start_depth, 1);
+        assert!(rows[510].highlight.is_empty());
+        assert!(rows[499].highlight.is_empty());
+    }
+
+    #[test]
+    fn refreshing_brackets_starts_from_the_row_above_when_it_is_checked() {
+        let syntax = RustHighlight::new();
+        let mut rows = rows(&"a(b)\n".repeat(20));
+        rows[0].row_content.insert(0, '{');
+        EditorRows::render_row(&mut rows[0]);
+        syntax.update_syntax(0, &mut rows);
+
+        assert_eq!(syntax.refresh_brackets(0..10, 1, &mut rows), 10);
+        assert_eq!(rows[9].end_state.start_depth, 1);
+        // 上の行は確かめてあるので、先頭から数え直さなくても深さがわかる
+        rows[0].end_state.brackets = Brackets::default();
+        assert_eq!(syntax.refresh_brackets(10..15, 10, &mut rows), 15);
+        assert_eq!(rows[14].end_state.start_depth, 1);
+        assert_eq!(bracket_color(&rows[14], 1), Some(BRACKET_COLORS[1]));
+    }
+
+    #[test]
+    fn opening_a_block_comment_rehighlights_the_following_rows() {
+        let syntax = RustHighlight::new();
+        let mut rows = rows("a\nb\n*/ c\nd");
+        rows[0].row_content.insert_str(0, "/*");
+        EditorRows::render_row(&mut rows[0]);
+        rows[3].highlight = vec![HighlightType::SearchMatch];
+        syntax.update_syntax(0, &mut rows);
+        assert!(rows[1].end_state.in_comment);
+        assert!(matches!(rows[2].highlight[0], HighlightType::Comment));
+        assert!(matches!(rows[2].highlight[3], HighlightType::Normal));
+        assert!(!rows[2].end_state.in_comment);
+        // コメントの外に戻った行の次からは塗り直さない
+        assert!(matches!(
+            rows[3].highlight[..],
+            [HighlightType::SearchMatch]
+        ));
+    }
+
+    #[test]
+    fn control_notations_are_not_brackets_or_numbers() {
+        let rows = rows("\x1b(\u{9b})");
+        assert_eq!(rows[0].render, "^[(<9b>)");
+        assert_eq!(rows[0].highlight.len(), rows[0].render.len());
+        assert!(rows[0].highlight[..2]
+            .iter()
+            .all(|it| matches!(it, HighlightType::Normal)));
+        assert!(rows[0].highlight[3..7]
+            .iter()
+            .all(|it| matches!(it, HighlightType::Normal)));
+        assert_eq!(bracket_color(&rows[0], 2), Some(BRACKET_COLORS[0]));
+        assert_eq!(bracket_color(&rows[0], 7), Some(BRACKET_COLORS[0]));
+        assert_eq!(rows[0].end_state.end_depth(), 0);
+    }
+
+    #[test]
+    fn number_literals_are_colored_whole() {
+        let rows = rows("let n = [0xFF, 0b1010, 0o17, 1_000, 1.5e10, 2.5E-3, 7u8, 0.5];");
+        let number = |it: &HighlightType| matches!(it, HighlightType::Number);
+        for literal in [
+            "0xFF", "0b1010", "0o17", "1_000", "1.5e10", "2.5E-3", "7u8", "0.5",
+        ] {
+            assert!(colored(&rows[0], literal, number), "{}", literal);
         }
-    };
+        let end = rows[0].render.find("0.5").unwrap() + 3;
+        assert!(!number(&rows[0].highlight[end]));
+    }
+
+    #[test]
+    fn digits_inside_identifiers_are_not_numbers() {
+        let rows = rows("x1 = utf8;");
+        assert!(!rows[0]
+            .highlight
+            .iter()
+            .any(|it| matches!(it, HighlightType::Number)));
+    }
+
+    #[test]
+    fn identifiers_before_a_parenthesis_are_functions() {
+        let rows = rows("foo(bar(baz)); if (x) {}");
+        let function = |it: &HighlightType| matches!(it, HighlightType::Function);
+        assert!(colored(&rows[0], "foo", function));
+        assert!(colored(&rows[0], "bar", function));
+        assert!(!colored(&rows[0], "baz", function));
+        // キーワードはキーワードの色のまま
+        assert!(colored(&rows[0], "if", |it| matches!(
+            it,
+            HighlightType::Other(_)
+        )));
+    }
+
+    #[test]
+    fn identifiers_before_a_bang_are_macros() {
+        let rows = rows("println!(\"x\"); if a != b {}");
+        let is_macro = |it: &HighlightType| matches!(it, HighlightType::Macro);
+        assert!(colored(&rows[0], "println!", is_macro));
+        assert!(colored(&rows[0], "\"x\"", |it| matches!(
+            it,
+            HighlightType::String
+        )));
+        assert!(!colored(&rows[0], "a", is_macro));
+    }
 }
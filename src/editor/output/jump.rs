@@ -0,0 +1,49 @@
+const MAX_JUMPS: usize = 100;
+
+/// 大きく移動する前のカーソル位置 `(y, x)` の履歴
+pub struct JumpList {
+    entries: Vec<(usize, usize)>,
+    index: usize,
+}
+
+impl JumpList {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            index: 0,
+        }
+    }
+
+    /// 移動前の位置を記録する。戻った先から記録すると、それより先の履歴は捨てる
+    pub fn push(&mut self, position: (usize, usize)) {
+        self.entries.truncate(self.index);
+        if self.entries.last() != Some(&position) {
+            self.entries.push(position);
+        }
+        if self.entries.len() > MAX_JUMPS {
+            self.entries.remove(0);
+        }
+        self.index = self.entries.len();
+    }
+
+    /// 1 つ前の位置を返す。履歴の末尾から戻るときは `current` を記録して戻ってこれるようにする
+    pub fn back(&mut self, current: (usize, usize)) -> Option<(usize, usize)> {
+        if self.index >= self.entries.len() {
+            self.push(current);
+            self.index = self.entries.len() - 1;
+        }
+        if self.index == 0 {
+            return None;
+        }
+        self.index -= 1;
+        Some(self.entries[self.index])
+    }
+
+    pub fn forward(&mut self) -> Option<(usize, usize)> {
+        if self.index + 1 >= self.entries.len() {
+            return None;
+        }
+        self.index += 1;
+        Some(self.entries[self.index])
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+
+use crossterm::event::KeyEvent;
+
+/// 記録したキー入力の並び。再生中は記録したキーを端末からの入力より先に返す
+pub struct KeyMacro {
+    /// 記録中のキー入力
+    recording: Option<Vec<KeyEvent>>,
+    /// 最後に記録し終えたキー入力
+    recorded: Vec<KeyEvent>,
+    /// 再生を待っているキー入力
+    pending: VecDeque<KeyEvent>,
+}
+
+impl KeyMacro {
+    pub fn new() -> Self {
+        Self {
+            recording: None,
+            recorded: Vec::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn start(&mut self) {
+        self.recording = Some(Vec::new());
+    }
+
+    /// 記録を終え、記録したキーの数を返す。直前のキー (記録を終えるキー) は含めない
+    pub fn stop(&mut self) -> usize {
+        let mut keys = self.recording.take().unwrap_or_default();
+        keys.pop();
+        self.recorded = keys;
+        self.recorded.len()
+    }
+
+    /// 記録中なら直前のキーを記録から取り除く
+    pub fn discard_last(&mut self) {
+        if let Some(keys) = self.recording.as_mut() {
+            keys.pop();
+        }
+    }
+
+    /// 最後に記録したキー入力を `times` 回分、再生待ちに積む。記録が無ければ `false` を返す
+    pub fn play(&mut self, times: usize) -> bool {
+        if self.recorded.is_empty() {
+            return false;
+        }
+        for _ in 0..times {
+            self.pending.extend(self.recorded.iter().copied());
+        }
+        true
+    }
+
+    /// 再生待ちのキーを 1 つ取り出す
+    pub fn pop(&mut self) -> Option<KeyEvent> {
+        self.pending.pop_front()
+    }
+
+    /// 記録中なら端末から読んだキーを記録する
+    pub fn record(&mut self, key_event: KeyEvent) {
+        if let Some(keys) = self.recording.as_mut() {
+            keys.push(key_event);
+        }
+    }
+}
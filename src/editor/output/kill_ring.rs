@@ -0,0 +1,80 @@
+const MAX_KILLS: usize = 16;
+
+/// `Ctrl-K` で削除したテキストの履歴。新しいものが末尾に来る
+pub struct KillRing {
+    kills: Vec<String>,
+    /// 貼り付けに使っている位置 (末尾から数える)
+    yank_index: usize,
+}
+
+impl KillRing {
+    pub fn new() -> Self {
+        Self {
+            kills: Vec::new(),
+            yank_index: 0,
+        }
+    }
+
+    /// 削除したテキストを記録する。`append` なら直前の記録に繋げる
+    pub fn push(&mut self, text: String, append: bool) {
+        match self.kills.last_mut() {
+            Some(last) if append => last.push_str(&text),
+            _ => {
+                self.kills.push(text);
+                if self.kills.len() > MAX_KILLS {
+                    self.kills.remove(0);
+                }
+            }
+        }
+        self.yank_index = 0;
+    }
+
+    /// 最も新しいテキスト
+    pub fn yank(&mut self) -> Option<&str> {
+        self.yank_index = 0;
+        self.kills.last().map(String::as_str)
+    }
+
+    /// 直前に貼り付けたものより 1 つ古いテキスト (最も古いものの次は最も新しいものに戻る)
+    pub fn rotate(&mut self) -> Option<&str> {
+        if self.kills.is_empty() {
+            return None;
+        }
+        self.yank_index = (self.yank_index + 1) % self.kills.len();
+        Some(&self.kills[self.kills.len() - 1 - self.yank_index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate_walks_back_through_older_kills_and_wraps() {
+        let mut ring = KillRing::new();
+        assert_eq!(ring.yank(), None);
+        assert_eq!(ring.rotate(), None);
+        ["one", "two", "three"].map(|text| ring.push(text.into(), false));
+
+        assert_eq!(ring.yank(), Some("three"));
+        assert_eq!(ring.rotate(), Some("two"));
+        assert_eq!(ring.rotate(), Some("one"));
+        assert_eq!(ring.rotate(), Some("three"));
+        // 貼り付け直すと最も新しいものから始める
+        ring.rotate();
+        assert_eq!(ring.yank(), Some("three"));
+    }
+
+    #[test]
+    fn push_appends_to_the_last_kill_and_drops_the_oldest() {
+        let mut ring = KillRing::new();
+        ring.push("a".into(), true);
+        ring.push("b".into(), true);
+        assert_eq!(ring.yank(), Some("ab"));
+
+        (0..MAX_KILLS).for_each(|i| ring.push(i.to_string(), false));
+        assert_eq!(ring.yank(), Some((MAX_KILLS - 1).to_string().as_str()));
+        assert_eq!(ring.kills.len(), MAX_KILLS);
+        assert_eq!(ring.kills[0], "0");
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+    os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    process,
+};
+
+const HEADER: &str = "pound-recovery 1";
+
+/// 保存していない変更の控え。編集中のファイルの内容とカーソル位置をユーザーごとのディレクトリに書き出す
+pub struct Snapshot {
+    pub contents: String,
+    /// カーソル位置 `(x, y)`
+    pub cursor: (usize, usize),
+}
+
+/// `$XDG_STATE_HOME/pound/recovery` (未設定なら `$HOME/.local/state/pound/recovery`)
+fn directory() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+        .map(|dir| dir.join("pound").join("recovery"))
+}
+
+/// 控えを置くディレクトリが自分の持ち物で、他のユーザーから読み書きできないことを確かめる
+fn check(dir: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(dir)?;
+    let private = metadata.is_dir()
+        && metadata.uid() == unsafe { libc::getuid() }
+        && metadata.mode() & 0o077 == 0;
+    if !private {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is not a private directory", dir.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// ファイルの絶対パスの `%` を `%25`、`/` を `%2F` に置き換えた控えの名前の前半。
+/// 置き換えは元に戻せるので、異なるファイルの控えの名前が重なることはない
+fn prefix(filename: &Path) -> String {
+    let path = fs::canonicalize(filename).unwrap_or_else(|_| {
+        env::current_dir()
+            .map(|dir| dir.join(filename))
+            .unwrap_or_else(|_| filename.to_path_buf())
+    });
+    format!("{}.", escape(&path.to_string_lossy()))
+}
+
+fn escape(path: &str) -> String {
+    path.replace('%', "%25").replace('/', "%2F")
+}
+
+/// このプロセスが `filename` の控えを書き出す場所。同じファイルを開いた他のエディタと
+/// 控えが重ならないようプロセス ID を付ける。`$HOME` も分からなければ `None`
+pub fn path(filename: &Path) -> Option<PathBuf> {
+    Some(directory()?.join(format!("{}{}.swp", prefix(filename), process::id())))
+}
+
+/// 控えを書き出す。ディレクトリは 0700、控えは 0600 で作る
+pub fn write(path: &Path, snapshot: &Snapshot) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    check(dir)?;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    write!(
+        file,
+        "{}\n{} {}\n{}",
+        HEADER, snapshot.cursor.0, snapshot.cursor.1, snapshot.contents
+    )
+}
+
+pub fn read(path: &Path) -> io::Result<Snapshot> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a recovery file");
+    let source = fs::read_to_string(path)?;
+    let (header, rest) = source.split_once('\n').ok_or_else(invalid)?;
+    let (cursor, contents) = rest.split_once('\n').ok_or_else(invalid)?;
+    if header != HEADER {
+        return Err(invalid());
+    }
+    let (x, y) = cursor.split_once(' ').ok_or_else(invalid)?;
+    Ok(Snapshot {
+        contents: contents.to_string(),
+        cursor: (
+            x.parse().map_err(|_| invalid())?,
+            y.parse().map_err(|_| invalid())?,
+        ),
+    })
+}
+
+/// 終了したエディタが残した `filename` の控えのうち最も新しいものを探す。
+/// まだ動いているエディタの控えは除く
+pub fn find(filename: &Path) -> Option<PathBuf> {
+    find_in(&directory()?, filename)
+}
+
+fn find_in(dir: &Path, filename: &Path) -> Option<PathBuf> {
+    check(dir).ok()?;
+    let prefix = prefix(filename);
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".swp"))
+                .and_then(|pid| pid.parse::<u32>().ok())
+                .is_some_and(|pid| pid != process::id() && !is_running(pid))
+        })
+        .max_by_key(|entry| entry.metadata().and_then(|it| it.modified()).ok())
+        .map(|entry| entry.path())
+}
+
+/// プロセスがまだ動いているか (`/proc` が無い環境では動いていないとみなす)
+fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("pound-recovery-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            contents: "one\ntwo".to_string(),
+            cursor: (1, 1),
+        }
+    }
+
+    #[test]
+    fn escape_keeps_different_paths_apart() {
+        assert_eq!(escape("/a/b"), "%2Fa%2Fb");
+        assert_eq!(escape("/a%2Fb"), "%2Fa%252Fb");
+        assert_ne!(escape("/a%b"), escape("/a/b"));
+    }
+
+    #[test]
+    fn write_creates_private_files() {
+        let dir = temp_dir("write");
+        let path = dir.join("nested").join("file.swp");
+        write(&path, &snapshot()).unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(path.parent().unwrap()), 0o700);
+        assert_eq!(mode(&path), 0o600);
+        let read = read(&path).unwrap();
+        assert_eq!(read.contents, "one\ntwo");
+        assert_eq!(read.cursor, (1, 1));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_refuses_a_directory_others_can_open() {
+        let dir = temp_dir("shared");
+        fs::create_dir_all(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+
+        let err = write(&dir.join("file.swp"), &snapshot()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(!dir.join("file.swp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn find_in_skips_snapshots_of_this_process() {
+        let dir = temp_dir("find");
+        let filename = Path::new("/nonexistent/file.txt");
+        let own = dir.join(format!("{}{}.swp", prefix(filename), process::id()));
+        write(&own, &snapshot()).unwrap();
+        assert_eq!(find_in(&dir, filename), None);
+
+        // PID の上限を超える番号のプロセスは動いていない
+        let left = dir.join(format!("{}{}.swp", prefix(filename), u32::MAX));
+        write(&left, &snapshot()).unwrap();
+        assert_eq!(find_in(&dir, filename), Some(left));
+        assert_eq!(find_in(&dir, Path::new("/nonexistent/file")), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,31 +1,186 @@
 use std::{
-    env, fs,
-    io::{self, Write},
-    path::PathBuf,
+    fs,
+    io::{self, Read, Write},
+    ops::Range,
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+    time::SystemTime,
 };
 
-use super::{
-    highlight::{HighlightType, SyntaxHighlight},
-    Output,
-};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use super::diff;
+use super::highlight::{HighlightType, LineState};
+
+/// タブの表示幅の既定値
+pub const TAB_STOP: usize = 8;
+
+/// タブの表示幅。`render_row` はどの行からも呼ばれるため設定を渡さずここに置く
+static TAB_WIDTH: AtomicUsize = AtomicUsize::new(TAB_STOP);
+
+pub fn tab_width() -> usize {
+    TAB_WIDTH.load(Ordering::Relaxed)
+}
+
+/// タブの表示幅を変える。既に作った `render` には反映されない
+pub fn set_tab_width(width: usize) {
+    TAB_WIDTH.store(width.max(1), Ordering::Relaxed);
+}
+
+/// タブの先頭に描く目印の文字 (0 なら描かない)
+static TAB_GUIDE: AtomicU32 = AtomicU32::new(0);
+
+pub fn tab_guide() -> Option<char> {
+    char::from_u32(TAB_GUIDE.load(Ordering::Relaxed)).filter(|c| *c != '\0')
+}
 
-const TAB_STOP: usize = 8;
+/// タブの先頭の目印を変える (`None` なら空白だけで埋める)。既に作った `render` には反映されない
+pub fn set_tab_guide(guide: Option<char>) {
+    TAB_GUIDE.store(guide.map_or(0, u32::from), Ordering::Relaxed);
+}
+
+const BOM: char = '\u{feff}';
+
+/// 保存時に行の区切りに使う改行コード
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+        }
+    }
+}
 
 pub struct EditorRows {
     pub row_contents: Vec<Row>,
     pub filename: Option<PathBuf>,
+    pub scratch: bool,
+    modified: Option<SystemTime>,
+    /// 読み込んだファイルが BOM で始まっていた (保存時に付け直す)
+    bom: bool,
+    pub line_ending: LineEnding,
+    /// 読み込んだファイルで改行コードが混在していた
+    pub mixed_line_endings: bool,
+    /// 読み込んだファイルが改行で終わっていた (保存時に付け直す)
+    final_newline: bool,
 }
 
-impl EditorRows {
-    pub fn new(syntax_highlight: &mut Option<Box<dyn SyntaxHighlight>>) -> Self {
-        let mut arg = env::args();
+/// `name` に標準入力の内容を書き込む `sudo tee`。SIGINT は既定の動作に戻して起動する
+fn sudo_tee(name: &Path) -> Command {
+    let mut command = Command::new("sudo");
+    command.arg("tee").arg("--").arg(name);
+    // SAFETY: `signal` は fork と exec の間でも呼べる
+    unsafe {
+        command.pre_exec(|| {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            Ok(())
+        });
+    }
+    command
+}
+
+/// `command` を起動して標準入力に `contents` を渡し、終了を待つ。
+/// 書き込みに失敗しても子プロセスを止めて待ち、ゾンビを残さない
+fn pipe_to(mut command: Command, contents: &[u8]) -> io::Result<()> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    // 書き終えたら標準入力を閉じて終わりを伝える
+    let written = child
+        .stdin
+        .take()
+        .map_or(Ok(()), |mut stdin| stdin.write_all(contents));
+    if written.is_err() {
+        let _ = child.kill();
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed ({})",
+            command.get_program().to_string_lossy(),
+            status
+        )));
+    }
+    written
+}
+
+/// 生きている間 SIGINT を無視し、終わったら元の動作に戻す
+struct IgnoreInterrupt(libc::sighandler_t);
+
+impl IgnoreInterrupt {
+    fn new() -> Self {
+        // SAFETY: 以前の動作は `drop` で戻す
+        Self(unsafe { libc::signal(libc::SIGINT, libc::SIG_IGN) })
+    }
+}
+
+impl Drop for IgnoreInterrupt {
+    fn drop(&mut self) {
+        // SAFETY: `new` で取り出した動作を戻すだけ
+        unsafe {
+            libc::signal(libc::SIGINT, self.0);
+        }
+    }
+}
 
-        match arg.nth(1) {
+/// 保存時に内容を整える設定
+#[derive(Clone, Copy, Default)]
+pub struct SaveOptions {
+    pub trim_trailing_whitespace: bool,
+    /// 元のファイルが改行で終わっていなくても最後に改行を付ける
+    pub insert_final_newline: bool,
+}
+
+impl EditorRows {
+    pub fn new(filename: Option<PathBuf>) -> Self {
+        match filename {
             None => Self {
                 row_contents: Vec::new(),
                 filename: None,
+                scratch: false,
+                modified: None,
+                bom: false,
+                line_ending: LineEnding::Lf,
+                mixed_line_endings: false,
+                final_newline: false,
             },
-            Some(file) => Self::from_file(file.into(), syntax_highlight),
+            Some(file) => Self::from_file(file),
+        }
+    }
+
+    /// 保存を促さないスクラッチバッファ。`read_stdin` なら標準入力の内容で始める
+    pub fn scratch(read_stdin: bool) -> Self {
+        let mut contents = String::new();
+        if read_stdin {
+            io::stdin()
+                .read_to_string(&mut contents)
+                .expect("Unable to read stdin");
+        }
+        Self {
+            row_contents: Self::rows_from(&contents),
+            filename: None,
+            scratch: true,
+            modified: None,
+            bom: false,
+            line_ending: LineEnding::Lf,
+            mixed_line_endings: false,
+            final_newline: false,
         }
     }
 
@@ -34,7 +189,11 @@ impl EditorRows {
             .as_ref()
             .and_then(|path| path.file_name())
             .and_then(|name| name.to_str())
-            .unwrap_or("[No Name]")
+            .unwrap_or(if self.scratch {
+                "[scratch]"
+            } else {
+                "[No Name]"
+            })
     }
 
     pub fn get_editor_row(&self, at: usize) -> &Row {
@@ -49,26 +208,146 @@ impl EditorRows {
         &self.row_contents[at].row_content
     }
 
-    fn from_file(file: PathBuf, syntax_highlight: &mut Option<Box<dyn SyntaxHighlight>>) -> Self {
-        let file_contents = fs::read_to_string(&file).expect("Unable to read file");
-        let mut row_contents = Vec::new();
+    fn from_file(file: PathBuf) -> Self {
+        Self::open(file).expect("Unable to read file")
+    }
+
+    /// ファイルを開く。まだ存在しないファイルは空のバッファとして開き、保存時に作成する
+    pub fn open(file: PathBuf) -> io::Result<Self> {
+        let file_contents = match fs::read_to_string(&file) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            contents => contents?,
+        };
+        let (contents, bom) = Self::strip_bom(&file_contents);
+        let (line_ending, mixed_line_endings) = Self::detect_line_ending(contents);
+        Ok(Self {
+            modified: Self::modified_time(&file),
+            filename: Some(file),
+            row_contents: Self::rows_from(contents),
+            scratch: false,
+            bom,
+            line_ending,
+            mixed_line_endings,
+            final_newline: contents.ends_with(['\n', '\r']),
+        })
+    }
+
+    fn strip_bom(contents: &str) -> (&str, bool) {
+        match contents.strip_prefix(BOM) {
+            Some(contents) => (contents, true),
+            None => (contents, false),
+        }
+    }
 
-        file.extension()
-            .and_then(|ext| ext.to_str())
-            .map(|ext| Output::select_syntax(ext).map(|syntax| syntax_highlight.insert(syntax)));
+    /// 最初に現れた改行コードと、他の改行コードが混ざっているかを返す。
+    /// 単独の `\r` は改行として数え、混在の扱いにする
+    fn detect_line_ending(contents: &str) -> (LineEnding, bool) {
+        let mut endings = Self::split_lines(contents)
+            .into_iter()
+            .filter_map(|(_, ending)| ending);
+        let first = endings.next().unwrap_or("\n");
+        let mixed = first == "\r" || endings.any(|ending| ending != first);
+        let line_ending = if first == "\r\n" {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        };
+        (line_ending, mixed)
+    }
 
-        file_contents.lines().enumerate().for_each(|(i, line)| {
-            let mut row = Row::new(line.into(), String::new());
-            Self::render_row(&mut row);
-            row_contents.push(row);
-            if let Some(it) = syntax_highlight {
-                it.update_syntax(i, &mut row_contents)
+    /// `\r\n`・`\n`・単独の `\r` のいずれでも行を区切り、各行と区切りを返す。
+    /// `str::lines` と同じく末尾の改行の後には行を作らない
+    fn split_lines(contents: &str) -> Vec<(&str, Option<&str>)> {
+        let mut lines = Vec::new();
+        let mut rest = contents;
+        while !rest.is_empty() {
+            match rest.find(['\r', '\n']) {
+                Some(at) => {
+                    let len = if rest[at..].starts_with("\r\n") { 2 } else { 1 };
+                    lines.push((&rest[..at], Some(&rest[at..at + len])));
+                    rest = &rest[at + len..];
+                }
+                None => {
+                    lines.push((rest, None));
+                    rest = "";
+                }
             }
-        });
-        Self {
-            filename: Some(file),
-            row_contents,
         }
+        lines
+    }
+
+    fn modified_time(file: &Path) -> Option<SystemTime> {
+        fs::metadata(file).and_then(|it| it.modified()).ok()
+    }
+
+    /// 読み込み (保存) した後にディスク上のファイルが更新されていれば `true` を返す。
+    /// 一度検出した更新は記録し、同じ更新を繰り返し報告しない
+    pub fn check_modified(&mut self) -> bool {
+        let modified = match &self.filename {
+            Some(name) => Self::modified_time(name),
+            None => return false,
+        };
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+
+    /// 保存されている内容とバッファを行単位で比べ、追加された行数と削除された行数を返す。
+    /// ファイルがまだ無ければすべての行を追加とみなす
+    pub fn changes_from_disk(&self) -> io::Result<(usize, usize)> {
+        let name = self
+            .filename
+            .as_ref()
+            .ok_or_else(|| io::Error::other("no file name specified"))?;
+        let on_disk = match fs::read_to_string(name) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let old: Vec<&str> = Self::split_lines(Self::strip_bom(&on_disk).0)
+            .into_iter()
+            .map(|(line, _)| line)
+            .collect();
+        let new: Vec<&str> = self
+            .row_contents
+            .iter()
+            .map(|row| row.row_content.as_str())
+            .collect();
+        Ok(diff::line_changes(&old, &new))
+    }
+
+    /// ディスク上のファイルを読み直す
+    pub fn reload(&mut self) -> io::Result<()> {
+        let name = self
+            .filename
+            .as_ref()
+            .ok_or_else(|| io::Error::other("no file name specified"))?;
+        let file_contents = fs::read_to_string(name)?;
+        let (contents, bom) = Self::strip_bom(&file_contents);
+        self.modified = Self::modified_time(name);
+        self.row_contents = Self::rows_from(contents);
+        self.bom = bom;
+        (self.line_ending, self.mixed_line_endings) = Self::detect_line_ending(contents);
+        self.final_newline = contents.ends_with(['\n', '\r']);
+        Ok(())
+    }
+
+    /// すべての行を `contents` で置き換える (ファイルの情報はそのまま)
+    pub fn set_contents(&mut self, contents: &str) {
+        self.row_contents = Self::rows_from(contents);
+    }
+
+    fn rows_from(contents: &str) -> Vec<Row> {
+        Self::split_lines(contents)
+            .into_iter()
+            .map(|(line, _)| {
+                let mut row = Row::new(line.into(), String::new());
+                Self::render_row(&mut row);
+                row
+            })
+            .collect()
     }
 
     pub fn get_render(&self, at: usize) -> &String {
@@ -85,49 +364,169 @@ impl EditorRows {
         &mut self.row_contents[at]
     }
 
-    pub fn save(&self) -> io::Result<usize> {
+    pub fn has_bom(&self) -> bool {
+        self.bom
+    }
+
+    /// 保存時に書き込む内容。改行コード・BOM・末尾の改行を元のファイルに合わせ、
+    /// `options` に従って整える。保存と書き込み内容の確認などで共有する
+    pub fn serialized_contents(&self, options: SaveOptions) -> String {
+        let mut contents: String = self
+            .row_contents
+            .iter()
+            .map(|it| {
+                if options.trim_trailing_whitespace {
+                    it.row_content.trim_end()
+                } else {
+                    it.row_content.as_str()
+                }
+            })
+            .collect::<Vec<&str>>()
+            .join(self.line_ending.as_str());
+        if self.bom {
+            contents.insert(0, BOM);
+        }
+        if (self.final_newline || options.insert_final_newline) && !self.row_contents.is_empty() {
+            contents.push_str(self.line_ending.as_str());
+        }
+        contents
+    }
+
+    pub fn save(&mut self, options: SaveOptions) -> io::Result<usize> {
         match &self.filename {
-            None => Err(io::Error::new(
-                io::ErrorKind::Other,
-                "no file name specified",
-            )),
+            None => Err(io::Error::other("no file name specified")),
             Some(name) => {
-                let mut file = fs::OpenOptions::new().write(true).create(true).open(name)?;
-                let contents: String = self
-                    .row_contents
-                    .iter()
-                    .map(|it| it.row_content.as_str())
-                    .collect::<Vec<&str>>()
-                    .join("\n");
+                let mut file = fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(name)?;
+                let contents = self.serialized_contents(options);
                 file.set_len(contents.len() as u64)?;
                 file.write_all(contents.as_bytes())?;
-                Ok(contents.as_bytes().len())
+                self.modified = Self::modified_time(name);
+                Ok(contents.len())
             }
         }
     }
 
+    /// `sudo tee` に内容を渡してファイルに書き込む。sudo はパスワードを端末から直接読むため、
+    /// 呼び出す側で生モードを解除しておく
+    pub fn save_with_sudo(&mut self, options: SaveOptions) -> io::Result<usize> {
+        let name = self
+            .filename
+            .clone()
+            .ok_or_else(|| io::Error::other("no file name specified"))?;
+        let contents = self.serialized_contents(options);
+        {
+            // 生モードでないので Ctrl-C は SIGINT になる。止めるのは sudo だけにする
+            let _interrupt = IgnoreInterrupt::new();
+            pipe_to(sudo_tee(&name), contents.as_bytes())?;
+        }
+        self.modified = Self::modified_time(&name);
+        Ok(contents.len())
+    }
+
+    /// 文字単位の `(x, y)` で指定した `start` から `end` の手前までのテキスト
+    pub fn text_range(&self, start: (usize, usize), end: (usize, usize)) -> String {
+        (start.1..=end.1)
+            .map(|y| {
+                let row = self.get_editor_row(y);
+                let from = if y == start.1 {
+                    row.byte_index(start.0)
+                } else {
+                    0
+                };
+                let to = if y == end.1 {
+                    row.byte_index(end.0)
+                } else {
+                    row.row_content.len()
+                };
+                &row.row_content[from..to]
+            })
+            .collect::<Vec<&str>>()
+            .join("\n")
+    }
+
+    /// 文字単位の `(x, y)` の位置にテキストを挿入し、挿入したテキストの末尾の位置を返す。
+    /// `\r\n` の `\r` は取り除く
+    pub fn insert_text(&mut self, at: (usize, usize), text: &str) -> (usize, usize) {
+        if at.1 == self.number_of_row() {
+            self.insert_row(at.1, String::new());
+        }
+        let row = self.get_editor_row_mut(at.1);
+        let tail = row.row_content.split_off(row.byte_index(at.0));
+        let mut lines = text
+            .split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line));
+        row.row_content.push_str(lines.next().unwrap_or_default());
+        Self::render_row(row);
+
+        let mut y = at.1;
+        for line in lines {
+            y += 1;
+            self.insert_row(y, line.into());
+        }
+        let row = self.get_editor_row_mut(y);
+        let x = row.char_len();
+        row.row_content.push_str(&tail);
+        Self::render_row(row);
+        (x, y)
+    }
+
+    /// 文字単位の `(x, y)` で指定した `start` から `end` の手前までを削除する
+    pub fn delete_range(&mut self, start: (usize, usize), end: (usize, usize)) {
+        let tail = {
+            let row = self.get_editor_row(end.1);
+            row.row_content[row.byte_index(end.0)..].to_string()
+        };
+        self.row_contents.drain(start.1 + 1..=end.1);
+        let row = self.get_editor_row_mut(start.1);
+        row.row_content.truncate(row.byte_index(start.0));
+        row.row_content.push_str(&tail);
+        Self::render_row(row);
+    }
+
+    /// `at` 行目を取り除き、その内容を返す
+    pub fn delete_row(&mut self, at: usize) -> String {
+        self.row_contents.remove(at).row_content
+    }
+
+    /// `at` 行目を前の行の末尾に連結する。先頭行や範囲外の行を指定したときは何もしない
     pub fn join_adjacent_rows(&mut self, at: usize) {
+        if at == 0 || at >= self.number_of_row() {
+            return;
+        }
         let current_row = self.row_contents.remove(at);
         let previous_row = self.get_editor_row_mut(at - 1);
         previous_row.row_content.push_str(&current_row.row_content);
         Self::render_row(previous_row);
     }
 
+    /// タブを展開し (`tab_guide` があれば先頭に目印を描く)、制御文字を `^[` や `<9b>` のような表記に置き換えて `render` を作る。
+    /// 行中に残った単独の `\r` や改ページ (`\x0c`) もそのまま出力せず `^M`・`^L` と表示する
     pub fn render_row(row: &mut Row) {
-        let mut index = 0;
-        let capacity = row
-            .row_content
-            .chars()
-            .fold(0, |acc, next| acc + if next == '\t' { TAB_STOP } else { 1 });
-        row.render = String::with_capacity(capacity);
+        row.render = String::with_capacity(row.row_content.len());
+        row.controls.clear();
+        row.tab_guides.clear();
+        row.overlay.clear();
+        let mut render_x = 0;
         row.row_content.chars().for_each(|c| {
-            index += 1;
+            let width = Row::render_width(c, render_x);
+            render_x += width;
             if c == '\t' {
-                row.render.push(' ');
-                while index % TAB_STOP != 0 {
-                    row.render.push(' ');
-                    index += 1;
+                let mut spaces = width;
+                if let Some(guide) = tab_guide() {
+                    let start = row.render.len();
+                    row.render.push(guide);
+                    row.tab_guides.push(start..row.render.len());
+                    spaces -= 1;
                 }
+                (0..spaces).for_each(|_| row.render.push(' '));
+            } else if let Some(notation) = Row::notation(c) {
+                let start = row.render.len();
+                row.render.push_str(&notation);
+                row.controls.push(start..row.render.len());
             } else {
                 row.render.push(c);
             }
@@ -140,6 +539,15 @@ pub struct Row {
     pub row_content: String,
     pub render: String,
     pub highlight: Vec<HighlightType>,
+    /// `highlight` の上に重ねて描画する一時的なハイライト (`render` のバイト範囲)。
+    /// 検索の一致などに使い、構文のハイライトは書き換えない。行を編集すると消える
+    pub overlay: Vec<(Range<usize>, HighlightType)>,
+    /// `render` 中の表記を置き換えた制御文字のバイト範囲
+    controls: Vec<Range<usize>>,
+    /// `render` 中のタブの先頭に描いた目印のバイト範囲
+    tab_guides: Vec<Range<usize>>,
+    /// 行末でのハイライトの状態 (次の行はこの状態から塗り始める)
+    pub end_state: LineState,
 }
 
 impl Row {
@@ -148,38 +556,360 @@ impl Row {
             row_content,
             render,
             highlight: Vec::new(),
+            overlay: Vec::new(),
+            controls: Vec::new(),
+            tab_guides: Vec::new(),
+            end_state: LineState::default(),
+        }
+    }
+
+    /// 制御文字の表記。C0 と DEL はキャレット表記 (`\x1b` なら `^[`、DEL なら `^?`)、
+    /// 端末が解釈してしまう C1 (U+0080–U+009F) は `<9b>` のような 16 進表記
+    fn notation(c: char) -> Option<String> {
+        match c {
+            '\t' => None,
+            '\x7f' => Some("^?".to_string()),
+            c if c.is_ascii_control() => Some(format!("^{}", (c as u8 | 0x40) as char)),
+            '\u{80}'..='\u{9f}' => Some(format!("<{:02x}>", c as u32)),
+            _ => None,
         }
     }
 
+    /// `render_x` の列に置いた `c` が画面上で占める列数 (全角文字は 2 列)
+    fn render_width(c: char, render_x: usize) -> usize {
+        if c == '\t' {
+            tab_width() - render_x % tab_width()
+        } else if let Some(notation) = Row::notation(c) {
+            notation.len()
+        } else {
+            c.width().unwrap_or(0)
+        }
+    }
+
+    /// 表記を置き換えた制御文字の `render` 上のバイト範囲
+    pub fn controls(&self) -> &[Range<usize>] {
+        &self.controls
+    }
+
+    /// タブの先頭に描いた目印の `render` 上のバイト範囲
+    pub fn tab_guides(&self) -> &[Range<usize>] {
+        &self.tab_guides
+    }
+
     pub fn insert_char(&mut self, at: usize, ch: char) {
+        let at = self.byte_index(at);
         self.row_content.insert(at, ch);
         EditorRows::render_row(self);
     }
 
+    pub fn replace_char(&mut self, at: usize, ch: char) {
+        let start = self.byte_index(at);
+        let end = self.byte_index(at + 1);
+        self.row_content
+            .replace_range(start..end, ch.encode_utf8(&mut [0; 4]));
+        EditorRows::render_row(self);
+    }
+
     pub fn delete_char(&mut self, at: usize) {
+        let at = self.byte_index(at);
         self.row_content.remove(at);
         EditorRows::render_row(self);
     }
 
+    /// 文字単位の範囲を削除する
+    pub fn delete_chars(&mut self, range: Range<usize>) {
+        let (start, end) = (self.byte_index(range.start), self.byte_index(range.end));
+        self.row_content.drain(start..end);
+        EditorRows::render_row(self);
+    }
+
+    /// 表示上の列の範囲に入る文字の範囲。行が `columns.start` まで届かなければ `None`
+    pub fn column_range(&self, columns: &Range<usize>) -> Option<Range<usize>> {
+        (self.render_x(self.char_len()) >= columns.start)
+            .then(|| self.get_row_content_x(columns.start)..self.get_row_content_x(columns.end))
+    }
+
+    /// `row_content` の文字数 (カーソルの `cursor_x` はこの単位で数える)
+    pub fn char_len(&self) -> usize {
+        self.row_content.chars().count()
+    }
+
+    /// 文字単位の位置を `row_content` のバイト位置に変換する
+    pub fn byte_index(&self, at: usize) -> usize {
+        self.row_content
+            .char_indices()
+            .nth(at)
+            .map_or(self.row_content.len(), |(i, _)| i)
+    }
+
+    /// 文字単位の位置をタブ展開後の `render` 上の表示上の列に変換する
+    pub fn render_x(&self, at: usize) -> usize {
+        self.row_content
+            .chars()
+            .take(at)
+            .fold(0, |acc, c| acc + Row::render_width(c, acc))
+    }
+
     pub fn find(&self, keyword: &str) -> Option<usize> {
         self.render.find(keyword)
     }
 
+    /// `render` のバイト長 (`render` や `highlight` を切り出すときに使う)
     pub fn len(&self) -> usize {
         self.render.len()
     }
 
+    /// `render` の表示幅 (`render_x` や `column_offset` はこの列の単位で数える)
+    pub fn display_width(&self) -> usize {
+        self.render.width()
+    }
+
+    /// 表示上の列 `column` 以降で最初に始まる文字の `render` 上のバイト位置
+    pub fn render_index(&self, column: usize) -> usize {
+        let mut current = 0;
+        for (i, c) in self.render.char_indices() {
+            if current >= column {
+                return i;
+            }
+            current += c.width().unwrap_or(0);
+        }
+        self.render.len()
+    }
+
+    /// 表示上の列の範囲 `columns` に収まる文字の `render` 上のバイト範囲と、
+    /// 範囲の先頭で幅の広い文字が途切れたときに代わりに埋める列数を返す
+    pub fn visible_range(&self, columns: Range<usize>) -> (Range<usize>, usize) {
+        let start = self.render_index(columns.start);
+        let padding = self.render[..start].width().saturating_sub(columns.start);
+        let mut current = columns.start + padding;
+        let mut end = self.render.len();
+        for (i, c) in self.render[start..].char_indices() {
+            current += c.width().unwrap_or(0);
+            if current > columns.end {
+                end = start + i;
+                break;
+            }
+        }
+        (start..end, padding.min(columns.len()))
+    }
+
     pub fn get_row_content_x(&self, render_x: usize) -> usize {
         let mut current_render_x = 0;
         for (cursor_x, ch) in self.row_content.chars().enumerate() {
-            if ch == '\t' {
-                current_render_x += (TAB_STOP - 1) - (current_render_x % TAB_STOP);
-            }
-            current_render_x += 1;
+            current_render_x += Row::render_width(ch, current_render_x);
             if current_render_x > render_x {
                 return cursor_x;
             }
         }
-        0
+        self.char_len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    #[test]
+    fn sudo_tee_passes_the_file_name_after_the_option_terminator() {
+        let command = sudo_tee(Path::new("-rf"));
+        assert_eq!(command.get_program(), "sudo");
+        assert_eq!(command.get_args().collect::<Vec<_>>(), ["tee", "--", "-rf"]);
+    }
+
+    #[test]
+    fn pipe_to_writes_the_contents_to_stdin() {
+        let path = env::temp_dir().join(format!("pound-pipe-test-{}", process::id()));
+        let mut command = Command::new("tee");
+        command.arg(&path);
+        pipe_to(command, "one\ntwo\n".as_bytes()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\n");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn pipe_to_reports_a_failing_command() {
+        let err = pipe_to(Command::new("false"), b"ignored").unwrap_err();
+        assert!(err.to_string().starts_with("false failed"), "{}", err);
+    }
+
+    #[test]
+    fn pipe_to_waits_when_the_command_stops_reading() {
+        // `true` は標準入力を読まずに終わるので書き込みは途中で失敗する
+        let contents = vec![b'x'; 1 << 20];
+        assert!(pipe_to(Command::new("true"), &contents).is_err());
+    }
+
+    #[test]
+    fn render_row_marks_c0_and_c1_controls() {
+        let mut row = Row::new(
+            "a\x00\x1b\x7f\u{85}\u{9f}\u{a0}b".to_string(),
+            String::new(),
+        );
+        EditorRows::render_row(&mut row);
+        assert_eq!(row.render, "a^@^[^?<85><9f>\u{a0}b");
+        assert_eq!(row.controls(), [1..3, 3..5, 5..7, 7..11, 11..15]);
+        // 表記の幅だけカーソルの列が進む
+        assert_eq!(row.render_x(4), 7);
+        assert_eq!(row.render_x(6), 15);
+        assert_eq!(row.get_row_content_x(8), 4);
+        assert_eq!(row.get_row_content_x(15), 6);
+    }
+
+    #[test]
+    fn char_positions_map_to_bytes_and_columns() {
+        let mut row = Row::new("a\tあé".to_string(), String::new());
+        EditorRows::render_row(&mut row);
+        assert_eq!(row.char_len(), 4);
+        assert_eq!(row.len(), "a       あé".len());
+        assert_eq!(
+            (0..=4).map(|at| row.byte_index(at)).collect::<Vec<_>>(),
+            [0, 1, 2, 5, 7]
+        );
+        assert_eq!(
+            (0..=4).map(|at| row.render_x(at)).collect::<Vec<_>>(),
+            [0, 1, 8, 10, 11]
+        );
+        // 幅の広い文字やタブの途中の列はその文字の位置になる
+        assert_eq!(
+            (0..=11)
+                .map(|x| row.get_row_content_x(x))
+                .collect::<Vec<_>>(),
+            [0, 1, 1, 1, 1, 1, 1, 1, 2, 2, 3, 4]
+        );
+    }
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("pound-row-test-{}-{}", process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn bom_is_stripped_on_load_and_restored_on_save() {
+        let contents = "\u{feff}first\r\nsecond\r\n".as_bytes();
+        let path = temp_file("bom", contents);
+        let mut rows = EditorRows::open(path.clone()).unwrap();
+        assert!(rows.has_bom());
+        assert_eq!(rows.get_row(0), "first");
+        assert_eq!(rows.number_of_row(), 2);
+
+        rows.save(SaveOptions::default()).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), contents);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn split_lines_accepts_crlf_lf_and_lone_cr() {
+        assert_eq!(
+            EditorRows::split_lines("a\r\nb\nc\rd"),
+            [
+                ("a", Some("\r\n")),
+                ("b", Some("\n")),
+                ("c", Some("\r")),
+                ("d", None)
+            ]
+        );
+        assert_eq!(
+            EditorRows::split_lines("a\n\n"),
+            [("a", Some("\n")), ("", Some("\n"))]
+        );
+        assert!(EditorRows::split_lines("").is_empty());
+    }
+
+    #[test]
+    fn mixed_line_endings_leave_no_carriage_return_in_rows() {
+        let path = temp_file("mixed", b"one\r\ntwo\nthree\rfour\r\n");
+        let mut rows = EditorRows::open(path.clone()).unwrap();
+        assert!(rows.mixed_line_endings);
+        assert!(rows.line_ending == LineEnding::Crlf);
+        assert_eq!(rows.number_of_row(), 4);
+        assert!(rows
+            .row_contents
+            .iter()
+            .all(|row| !row.row_content.contains('\r')));
+
+        rows.line_ending = LineEnding::Lf;
+        rows.save(SaveOptions::default()).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "one\ntwo\nthree\nfour\n"
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn serialized_contents_follows_each_setting() {
+        let mut rows = EditorRows::new(None);
+        rows.set_contents("a  \nb");
+        let trim = SaveOptions {
+            trim_trailing_whitespace: true,
+            ..SaveOptions::default()
+        };
+        let final_newline = SaveOptions {
+            insert_final_newline: true,
+            ..SaveOptions::default()
+        };
+        assert_eq!(rows.serialized_contents(SaveOptions::default()), "a  \nb");
+        assert_eq!(rows.serialized_contents(trim), "a\nb");
+        assert_eq!(rows.serialized_contents(final_newline), "a  \nb\n");
+
+        rows.final_newline = true;
+        rows.line_ending = LineEnding::Crlf;
+        rows.bom = true;
+        assert_eq!(
+            rows.serialized_contents(SaveOptions::default()),
+            "\u{feff}a  \r\nb\r\n"
+        );
+
+        // 空のバッファには改行を付けない
+        rows.set_contents("");
+        assert_eq!(rows.serialized_contents(final_newline), "\u{feff}");
+    }
+
+    #[test]
+    fn form_feed_and_lone_carriage_return_are_shown_as_carets() {
+        let mut row = Row::new("\x0cpage\rend".to_string(), String::new());
+        EditorRows::render_row(&mut row);
+        assert_eq!(row.render, "^Lpage^Mend");
+        assert_eq!(row.controls(), [0..2, 6..8]);
+        assert_eq!(row.render_x(row.char_len()), 11);
+    }
+
+    #[test]
+    fn insert_text_drops_the_cr_of_crlf() {
+        let mut rows = EditorRows::new(None);
+        rows.set_contents("[]");
+        let end = rows.insert_text((1, 0), "one\r\ntwo\r\n");
+        assert_eq!(end, (0, 2));
+        let lines: Vec<_> = (0..rows.number_of_row())
+            .map(|at| rows.get_row(at))
+            .collect();
+        assert_eq!(lines, ["[one", "two", "]"]);
+    }
+
+    #[test]
+    fn visible_range_pads_a_wide_character_cut_at_the_left_edge() {
+        let mut row = Row::new("aあいb".to_string(), String::new());
+        EditorRows::render_row(&mut row);
+        // 2 列目は `あ` の右半分なので空白 1 つで埋め、`い` から描く
+        assert_eq!(row.visible_range(2..5), (4..7, 1));
+        assert_eq!(row.visible_range(0..2), (0..1, 0));
+        assert_eq!(row.visible_range(1..3), (1..4, 0));
+        assert_eq!(row.visible_range(10..20), (8..8, 0));
+    }
+
+    #[test]
+    fn changes_from_disk_treats_a_missing_file_as_empty() {
+        let path = env::temp_dir().join(format!("pound-row-test-{}-missing", process::id()));
+        let mut rows = EditorRows::open(path.clone()).unwrap();
+        rows.set_contents("one\ntwo");
+        assert_eq!(rows.changes_from_disk().unwrap(), (2, 0));
+        assert!(!path.exists());
+
+        rows.save(SaveOptions::default()).unwrap();
+        rows.set_contents("one\n2\nthree");
+        assert_eq!(rows.changes_from_disk().unwrap(), (2, 1));
+        fs::remove_file(path).unwrap();
     }
 }
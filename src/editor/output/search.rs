@@ -1,16 +1,120 @@
-use super::highlight::HighlightType;
+use crossterm::event::{KeyCode, KeyModifiers};
 
+use crate::editor::config;
+
+#[derive(Copy, Clone, PartialEq)]
 pub enum SearchDirection {
     Forward,
     Backward,
 }
 
+#[derive(Copy, Clone, PartialEq)]
+pub enum SearchAction {
+    Accept,
+    Cancel,
+    NextLine,
+    PreviousLine,
+    NextMatch,
+    PreviousMatch,
+    Help,
+}
+
+/// 検索中のキーと操作の対応 (プロンプトのヒントやヘルプもここから作る)
+pub const SEARCH_KEYS: [(KeyCode, SearchAction); 7] = [
+    (KeyCode::Enter, SearchAction::Accept),
+    (KeyCode::Esc, SearchAction::Cancel),
+    (KeyCode::Down, SearchAction::NextLine),
+    (KeyCode::Up, SearchAction::PreviousLine),
+    (KeyCode::Right, SearchAction::NextMatch),
+    (KeyCode::Left, SearchAction::PreviousMatch),
+    (KeyCode::F(1), SearchAction::Help),
+];
+
+impl SearchAction {
+    pub fn from_key(code: KeyCode) -> Option<Self> {
+        SEARCH_KEYS
+            .iter()
+            .find(|(key, _)| *key == code)
+            .map(|(_, action)| *action)
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            SearchAction::Accept => "accept",
+            SearchAction::Cancel => "cancel",
+            SearchAction::NextLine => "next line",
+            SearchAction::PreviousLine => "previous line",
+            SearchAction::NextMatch => "next match in line",
+            SearchAction::PreviousMatch => "previous match in line",
+            SearchAction::Help => "key help",
+        }
+    }
+
+    fn key_name(&self) -> String {
+        SEARCH_KEYS
+            .iter()
+            .filter(|(_, action)| action == self)
+            .map(|(code, _)| config::key_name(*code, KeyModifiers::NONE))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// プロンプトに添える短いヒント
+    pub fn hint() -> String {
+        [
+            SearchAction::Cancel,
+            SearchAction::Accept,
+            SearchAction::Help,
+        ]
+        .iter()
+        .map(|action| format!("{} = {}", action.key_name(), action.description()))
+        .collect::<Vec<_>>()
+        .join(" | ")
+    }
+
+    /// すべてのキーの説明
+    pub fn help() -> Vec<String> {
+        SEARCH_KEYS
+            .iter()
+            .map(|(code, action)| {
+                format!(
+                    "{:<8} {}",
+                    config::key_name(*code, KeyModifiers::NONE),
+                    action.description()
+                )
+            })
+            .collect()
+    }
+}
+
 pub struct SearchIndex {
     pub x_index: usize,
     pub y_index: usize,
+    pub start_x: usize,
+    pub start_y: usize,
     pub x_direction: Option<SearchDirection>,
     pub y_direction: Option<SearchDirection>,
-    pub previous_highlight: Option<(usize, Vec<HighlightType>)>,
+    /// 一致を `overlay` に重ねている行
+    pub overlay_row: Option<usize>,
+    /// `matches` を数えたときのキーワード
+    pub keyword: String,
+    /// キーワードに一致した位置 (行, `render` のバイト位置) の一覧
+    pub matches: Vec<(usize, usize)>,
+    /// 現在の一致が `matches` の何番目か
+    pub current: Option<usize>,
+    /// 最後に確定した検索語 (`reset` では消さない)
+    pub last_keyword: Option<String>,
+    /// 最後の検索で最後に使った矢印キーの向き (`reset` では消さない)
+    pub last_direction: SearchDirection,
+}
+
+impl SearchDirection {
+    pub fn reverse(self) -> Self {
+        match self {
+            SearchDirection::Forward => SearchDirection::Backward,
+            SearchDirection::Backward => SearchDirection::Forward,
+        }
+    }
 }
 
 impl SearchIndex {
@@ -18,17 +122,29 @@ impl SearchIndex {
         Self {
             x_index: 0,
             y_index: 0,
+            start_x: 0,
+            start_y: 0,
             x_direction: None,
             y_direction: None,
-            previous_highlight: None,
+            overlay_row: None,
+            keyword: String::new(),
+            matches: Vec::new(),
+            current: None,
+            last_keyword: None,
+            last_direction: SearchDirection::Forward,
         }
     }
 
     pub fn reset(&mut self) {
         self.x_index = 0;
         self.y_index = 0;
+        self.start_x = 0;
+        self.start_y = 0;
         self.x_direction = None;
         self.y_direction = None;
-        self.previous_highlight = None;
+        self.overlay_row = None;
+        self.keyword.clear();
+        self.matches.clear();
+        self.current = None;
     }
 }
@@ -3,19 +3,25 @@ use std::time::{Duration, Instant};
 pub struct StatusMessage {
     message: Option<String>,
     set_time: Option<Instant>,
+    /// `None` なら次のメッセージで置き換えられるまで表示し続ける
+    timeout: Option<Duration>,
+    /// `set_message` で表示したメッセージが消えるまでの時間
+    default_timeout: Option<Duration>,
 }
 
 impl StatusMessage {
-    pub fn new(initial_message: String) -> Self {
+    pub fn new(default_timeout: Option<Duration>) -> Self {
         Self {
-            message: Some(initial_message),
-            set_time: Some(Instant::now()),
+            message: None,
+            set_time: None,
+            timeout: default_timeout,
+            default_timeout,
         }
     }
 
     pub fn message(&mut self) -> Option<&String> {
         self.set_time.and_then(|time| {
-            if time.elapsed() > Duration::from_secs(5) {
+            if self.timeout.is_some_and(|timeout| time.elapsed() > timeout) {
                 self.message = None;
                 self.set_time = None;
                 None
@@ -26,7 +32,12 @@ impl StatusMessage {
     }
 
     pub fn set_message(&mut self, message: String) {
+        self.set_message_for(message, self.default_timeout)
+    }
+
+    pub fn set_message_for(&mut self, message: String, timeout: Option<Duration>) {
         self.message = Some(message);
-        self.set_time = Some(Instant::now())
+        self.set_time = Some(Instant::now());
+        self.timeout = timeout;
     }
 }
@@ -0,0 +1,227 @@
+use std::{fs, path::Path, rc::Rc};
+
+use crossterm::style::Color;
+
+use crate::editor::config::{self, Value};
+
+use super::highlight::SyntaxHighlight;
+
+/// 設定ファイルで定義するシンタックス
+///
+/// ```toml
+/// file_type = "mylang"
+/// extensions = ["mylang"]
+/// comment_start = "#"
+/// multiline_comment = ["=begin", "=end"]
+///
+/// [keywords]
+/// red = ["fn", "let"]
+/// yellow = ["int", "str"]
+/// ```
+pub struct SyntaxDefinition {
+    extensions: Vec<String>,
+    file_type: String,
+    comment_start: String,
+    multiline_comment: Option<(String, String)>,
+    keywords: Vec<(Color, Vec<String>)>,
+}
+
+impl SyntaxDefinition {
+    /// 設定ディレクトリの `syntax/*.toml` からシンタックスを読み込む。
+    /// 読み込めなかった項目は警告として返す
+    pub fn load_all() -> (Vec<Rc<dyn SyntaxHighlight>>, Vec<String>) {
+        let mut syntaxes: Vec<Rc<dyn SyntaxHighlight>> = Vec::new();
+        let mut warnings = Vec::new();
+
+        let entries = match config::config_dir().map(|dir| fs::read_dir(dir.join("syntax"))) {
+            Some(Ok(entries)) => entries,
+            _ => return (syntaxes, warnings),
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            match SyntaxDefinition::from_file(&path, &mut warnings) {
+                Ok(syntax) => syntaxes.push(Rc::new(syntax)),
+                Err(err) => warnings.push(format!("{}: {}", path.display(), err)),
+            }
+        }
+        (syntaxes, warnings)
+    }
+
+    fn from_file(path: &Path, warnings: &mut Vec<String>) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let mut syntax = Self {
+            extensions: Vec::new(),
+            file_type: path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or_default()
+                .to_string(),
+            comment_start: String::new(),
+            multiline_comment: None,
+            keywords: Vec::new(),
+        };
+
+        for (key, value) in config::parse(&source)? {
+            match (key.as_str(), &value) {
+                ("file_type", Value::String(file_type)) => syntax.file_type = file_type.clone(),
+                ("comment_start", Value::String(start)) => syntax.comment_start = start.clone(),
+                ("multiline_comment", Value::Array(_)) => match Self::strings(&value).as_deref() {
+                    Some([start, end]) if !start.is_empty() && !end.is_empty() => {
+                        syntax.multiline_comment = Some((start.clone(), end.clone()))
+                    }
+                    _ => warnings.push(format!(
+                        "{}: `multiline_comment` must be a start and an end string",
+                        path.display()
+                    )),
+                },
+                ("extensions", Value::Array(_)) => match Self::strings(&value) {
+                    Some(extensions) => syntax.extensions = extensions,
+                    None => {
+                        warnings.push(format!("{}: `extensions` must be strings", path.display()))
+                    }
+                },
+                (key, Value::Array(_)) if key.starts_with("keywords.") => {
+                    let name = &key["keywords.".len()..];
+                    match (Self::color(name), Self::strings(&value)) {
+                        (Some(color), Some(words)) => syntax.keywords.push((color, words)),
+                        (None, _) => {
+                            warnings.push(format!("{}: unknown color `{}`", path.display(), name))
+                        }
+                        (_, None) => warnings.push(format!(
+                            "{}: keywords for `{}` must be strings",
+                            path.display(),
+                            name
+                        )),
+                    }
+                }
+                (key, _) => warnings.push(format!("{}: ignored `{}`", path.display(), key)),
+            }
+        }
+
+        if syntax.extensions.is_empty() {
+            return Err("no `extensions` specified".into());
+        }
+        Ok(syntax)
+    }
+
+    fn strings(value: &Value) -> Option<Vec<String>> {
+        value
+            .as_array()?
+            .iter()
+            .map(|value| value.as_str().map(String::from))
+            .collect()
+    }
+
+    fn color(name: &str) -> Option<Color> {
+        if name.eq_ignore_ascii_case("reset") {
+            return Some(Color::Reset);
+        }
+        Color::try_from(name).ok()
+    }
+}
+
+impl SyntaxHighlight for SyntaxDefinition {
+    fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+
+    fn file_type(&self) -> &str {
+        &self.file_type
+    }
+
+    fn comment_start(&self) -> &str {
+        &self.comment_start
+    }
+
+    fn multiline_comment(&self) -> Option<(&str, &str)> {
+        self.multiline_comment
+            .as_ref()
+            .map(|(start, end)| (start.as_str(), end.as_str()))
+    }
+
+    fn keywords(&self) -> &[(Color, Vec<String>)] {
+        &self.keywords
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn load(name: &str, source: &str) -> (Result<SyntaxDefinition, String>, Vec<String>) {
+        let path =
+            env::temp_dir().join(format!("pound-syntax-test-{}-{}.toml", process::id(), name));
+        fs::write(&path, source).unwrap();
+        let mut warnings = Vec::new();
+        let syntax = SyntaxDefinition::from_file(&path, &mut warnings);
+        fs::remove_file(&path).unwrap();
+        let prefix = format!("{}: ", path.display());
+        let warnings = warnings
+            .iter()
+            .map(|warning| warning.strip_prefix(&prefix).unwrap().to_string())
+            .collect();
+        (syntax, warnings)
+    }
+
+    #[test]
+    fn from_file_reads_a_definition() {
+        let (syntax, warnings) = load(
+            "mylang",
+            "file_type = \"MyLang\"\n\
+             extensions = [\"mylang\", \"ml\"]\n\
+             comment_start = \"--\"\n\
+             multiline_comment = [\"{-\", \"-}\"]\n\
+             [keywords]\n\
+             red = [\"fn\", \"let\"]\n\
+             reset = [\"plain\"]\n",
+        );
+        let syntax = syntax.unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(syntax.file_type(), "MyLang");
+        assert_eq!(syntax.extensions(), ["mylang", "ml"]);
+        assert_eq!(syntax.comment_start(), "--");
+        assert_eq!(syntax.multiline_comment(), Some(("{-", "-}")));
+        assert_eq!(syntax.keywords().len(), 2);
+        assert_eq!(syntax.keywords()[0].0, Color::Red);
+        assert_eq!(syntax.keywords()[1].1, ["plain"]);
+    }
+
+    #[test]
+    fn from_file_skips_malformed_entries_with_warnings() {
+        let (syntax, warnings) = load(
+            "broken",
+            "extensions = [\"x\"]\n\
+             multiline_comment = [\"/*\"]\n\
+             indent = 4\n\
+             [keywords]\n\
+             purplish = [\"a\"]\n\
+             blue = [\"b\", 1]\n",
+        );
+        let syntax = syntax.unwrap();
+        assert!(syntax.keywords().is_empty());
+        assert_eq!(syntax.multiline_comment(), None);
+        assert_eq!(
+            warnings,
+            [
+                "`multiline_comment` must be a start and an end string",
+                "ignored `indent`",
+                "unknown color `purplish`",
+                "keywords for `blue` must be strings",
+            ]
+        );
+    }
+
+    #[test]
+    fn from_file_needs_extensions() {
+        let (syntax, _) = load("empty", "comment_start = \"#\"");
+        assert_eq!(syntax.err().unwrap(), "no `extensions` specified");
+        let (syntax, _) = load("invalid", "extensions = [");
+        assert_eq!(syntax.err().unwrap(), "line 1: unclosed array");
+    }
+}
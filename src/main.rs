@@ -1,19 +1,19 @@
 mod editor;
 
-use crossterm::terminal;
-use editor::Editor;
-
-struct CleanUp;
-
-impl Drop for CleanUp {
-    fn drop(&mut self) {
-        terminal::disable_raw_mode().expect("Could not turn off raw mode");
-    }
-}
+use editor::{Args, Editor, Reader};
+use std::{env, process};
 
 // comment
 fn main() -> crossterm::Result<()> {
-    let mut editor = Editor::new();
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    };
+
+    let mut editor = Editor::new(args, Reader);
     editor.init()?;
 
     while editor.run()? {}